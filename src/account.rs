@@ -1,18 +1,21 @@
+use std::sync::Arc;
+
 use askama::Template;
-use askama_axum::IntoResponse;
-use axum::{extract::Form, http::StatusCode, response::Response};
-use axum_extra::extract::cookie::PrivateCookieJar;
+use axum::extract::{Form, Path, State};
 use qrcode::render::svg;
 use qrcode::QrCode;
 use serde::Deserialize;
 use surrealdb::sql::Thing;
 
-use crate::DB;
+use crate::{
+    auth::CurrentUser, config::Config, db::Timed, error::AppError, payment::Payments, seatmap, DB,
+};
 
 #[derive(Template)]
 #[template(path = "tickets.html")]
 pub struct Tickets {
     tickets: Vec<TicketInfo>,
+    verified: bool,
 }
 
 #[derive(Template)]
@@ -27,15 +30,20 @@ pub struct Ticket {
     time: String,
     seat: i32,
     id: Thing,
+    layout: Option<Vec<String>>,
+    cancelled: bool,
+    cancellable: bool,
 }
 
 #[derive(Deserialize)]
 pub struct TicketInfo {
     movie: String,
     time: String,
-    seat: i32,
+    seat: String,
     id: String,
     svg: String,
+    cancelled: bool,
+    cancellable: bool,
 }
 
 impl TicketInfo {
@@ -45,8 +53,12 @@ impl TicketInfo {
             time,
             seat,
             id,
+            layout,
+            cancelled,
+            cancellable,
         }: Ticket,
     ) -> Self {
+        let seat = seatmap::layout(layout).label(seat);
         let id = id.id.to_raw();
         let code = QrCode::new(id.as_bytes()).unwrap();
         let svg = code
@@ -61,6 +73,8 @@ impl TicketInfo {
             seat,
             id,
             svg,
+            cancelled,
+            cancellable,
         }
     }
 }
@@ -70,73 +84,172 @@ pub struct Query {
     query: String,
 }
 
-pub async fn search_tickets(jar: PrivateCookieJar, Form(Query { query }): Form<Query>) -> Response {
-    let Some(session) = jar.get("session") else {
-        return StatusCode::UNAUTHORIZED.into_response();
-    };
-    let query = DB
+pub async fn search_tickets(
+    user: CurrentUser,
+    State(config): State<Arc<Config>>,
+    Form(Query { query }): Form<Query>,
+) -> Result<SearchResults, AppError> {
+    let mut query = DB
         .query(
             r#"
-            SELECT seat, 
-            (<-purchase[0].id) AS id, 
-            (<-showtime_seat<-showtime<-showing<-theaters<-playing<-movies.name)[0] AS movie, 
-            time::format((<-showtime_seat<-showtime.time)[0], "%k:%M, %x") AS time
-            FROM (
-                SELECT VALUE ->account_session->accounts->purchase->seats 
-                FROM ONLY type::thing("sessions", $id)
+            SELECT id, out.seat AS seat,
+            (out<-showtime_seat<-showtime<-showing<-theaters<-playing<-movies.name)[0] AS movie,
+            time::format((out<-showtime_seat<-showtime.time)[0], "%k:%M, %x") AS time,
+            (out<-showtime_seat<-showtime<-showing<-theaters.layout)[0] AS layout,
+            cancelled_at != NONE AS cancelled,
+            cancelled_at = NONE AND order.payment_reference != NONE
+                AND (out<-showtime_seat<-showtime.time)[0] > time::now() + <duration> $cutoff AS cancellable
+            FROM purchase
+            WHERE in = $account AND (
+                string::contains(string::lowercase((out<-showtime_seat<-showtime<-showing<-theaters<-playing<-movies.name)[0]), string::lowercase($query)) ||
+                string::contains(time::format((out<-showtime_seat<-showtime.time)[0], "%k:%M, %x"), $query) ||
+                type::string(out.seat) = $query ||
+                string::contains(type::string(id), $query)
             )
-            WHERE string::contains(string::lowercase((<-showtime_seat<-showtime<-showing<-theaters<-playing<-movies.name)[0]), string::lowercase($query)) ||
-            string::contains(time::format((<-showtime_seat<-showtime.time)[0], "%k:%M, %x"), $query) ||
-            type::string(seat) = $query ||
-            string::contains(type::string(<-purchase[0].id), $query)
             ORDER BY time;
             "#,
         )
-        .bind(("id", session.value()))
+        .bind(("account", &user.account))
         .bind(("query", query))
-        .await;
-    let Ok(mut query) = query else {
-        return StatusCode::UNAUTHORIZED.into_response();
-    };
-    let Ok(tickets) = query.take::<Vec<Ticket>>(0) else {
-        return StatusCode::NOT_ACCEPTABLE.into_response();
-    };
+        .bind(("cutoff", cutoff(&config)))
+        .timed()
+        .await?;
+    let tickets = query.take::<Vec<Ticket>>(0)?;
     let tickets = tickets
         .into_iter()
         .map(|ticket| TicketInfo::from_ticket(ticket))
         .collect();
-    SearchResults { tickets }.into_response()
+    Ok(SearchResults { tickets })
 }
 
-pub async fn tickets(jar: PrivateCookieJar) -> Response {
-    let Some(session) = jar.get("session") else {
-        return StatusCode::UNAUTHORIZED.into_response();
-    };
-    let query = DB
+pub async fn tickets(
+    user: CurrentUser,
+    State(config): State<Arc<Config>>,
+) -> Result<Tickets, AppError> {
+    let mut query = DB
         .query(
             r#"
-            SELECT seat, 
-            (<-purchase[0].id) AS id, 
-            (<-showtime_seat<-showtime<-showing<-theaters<-playing<-movies.name)[0] AS movie, 
-            time::format((<-showtime_seat<-showtime.time)[0], "%k:%M, %x") AS time
-            FROM (
-                SELECT VALUE ->account_session->accounts->purchase->seats 
-                FROM ONLY type::thing("sessions", $id)
-            )
+            SELECT id, out.seat AS seat,
+            (out<-showtime_seat<-showtime<-showing<-theaters<-playing<-movies.name)[0] AS movie,
+            time::format((out<-showtime_seat<-showtime.time)[0], "%k:%M, %x") AS time,
+            (out<-showtime_seat<-showtime<-showing<-theaters.layout)[0] AS layout,
+            cancelled_at != NONE AS cancelled,
+            cancelled_at = NONE AND order.payment_reference != NONE
+                AND (out<-showtime_seat<-showtime.time)[0] > time::now() + <duration> $cutoff AS cancellable
+            FROM purchase
+            WHERE in = $account
             ORDER BY time;
             "#,
         )
-        .bind(("id", session.value()))
-        .await;
-    let Ok(mut query) = query else {
-        return StatusCode::UNAUTHORIZED.into_response();
-    };
-    let Ok(tickets) = query.take::<Vec<Ticket>>(0) else {
-        return StatusCode::NOT_ACCEPTABLE.into_response();
-    };
+        .bind(("account", &user.account))
+        .bind(("cutoff", cutoff(&config)))
+        .timed()
+        .await?;
+    let tickets = query.take::<Vec<Ticket>>(0)?;
     let tickets = tickets
         .into_iter()
         .map(|ticket| TicketInfo::from_ticket(ticket))
         .collect();
-    Tickets { tickets }.into_response()
+    Ok(Tickets {
+        tickets,
+        verified: user.verified,
+    })
+}
+
+#[derive(Deserialize)]
+struct Refund {
+    price: Option<u32>,
+    reference: Option<String>,
+    cancelled: bool,
+    cancellable: bool,
+}
+
+// the ticket is marked cancelled before the refund so it can only be refunded
+// once, and the seat goes back on sale only after the money has
+pub async fn cancel_ticket(
+    user: CurrentUser,
+    State(config): State<Arc<Config>>,
+    State(payments): State<Payments>,
+    Path(id): Path<String>,
+) -> Result<Tickets, AppError> {
+    let ticket = Thing::from(("purchase", id.as_str()));
+    let mut query = DB
+        .query(
+            r#"
+            SELECT price, order.payment_reference AS reference,
+            cancelled_at != NONE AS cancelled,
+            (out<-showtime_seat<-showtime.time)[0] > time::now() + <duration> $cutoff AS cancellable
+            FROM ONLY $ticket
+            WHERE in = $account
+            "#,
+        )
+        .bind(("ticket", &ticket))
+        .bind(("account", &user.account))
+        .bind(("cutoff", cutoff(&config)))
+        .timed()
+        .await?;
+    let Some(refund) = query.take::<Option<Refund>>(0)? else {
+        return Err(AppError::NotFound);
+    };
+    if refund.cancelled {
+        return Err(AppError::Conflict(
+            "This ticket has already been cancelled.".to_string(),
+        ));
+    }
+    if !refund.cancellable {
+        return Err(AppError::BadRequest(format!(
+            "Tickets can only be cancelled up to {} minutes before the show.",
+            config.cancellation.cutoff_minutes
+        )));
+    }
+    let (Some(price), Some(reference)) = (refund.price, refund.reference) else {
+        return Err(AppError::BadRequest(
+            "This ticket can't be cancelled online.".to_string(),
+        ));
+    };
+
+    let mut query = DB
+        .query("UPDATE $ticket SET cancelled_at = time::now() WHERE cancelled_at = NONE")
+        .bind(("ticket", &ticket))
+        .timed()
+        .await?;
+    let claimed: Vec<Thing> = query.take((0, "id"))?;
+    if claimed.is_empty() {
+        return Err(AppError::Conflict(
+            "This ticket has already been cancelled.".to_string(),
+        ));
+    }
+
+    if payments.refund(&reference, price).await.is_err() {
+        DB.query("UPDATE $ticket SET cancelled_at = NONE")
+            .bind(("ticket", &ticket))
+            .timed()
+            .await?
+            .check()?;
+        return Err(AppError::Unavailable(
+            "Your ticket could not be refunded right now and has not been cancelled.".to_string(),
+        ));
+    }
+
+    DB.query(
+        r#"
+        BEGIN TRANSACTION;
+
+        UPDATE $ticket SET refunded = $price;
+        UPDATE (SELECT VALUE out FROM ONLY $ticket) SET available = true;
+
+        COMMIT TRANSACTION;
+        "#,
+    )
+    .bind(("ticket", &ticket))
+    .bind(("price", price))
+    .timed()
+    .await?
+    .check()?;
+
+    tickets(user, State(config)).await
+}
+
+fn cutoff(config: &Config) -> String {
+    format!("{}m", config.cancellation.cutoff_minutes)
 }
@@ -0,0 +1,189 @@
+use std::{env, io};
+
+use anyhow::{bail, Context};
+use askama::Template;
+use askama_axum::IntoResponse;
+use axum::{extract::Form, response::Response};
+use axum_htmx::HxRequest;
+use serde::Deserialize;
+use surrealdb::sql::Thing;
+
+use crate::{
+    csrf::CsrfToken,
+    db::Timed,
+    error::AppError,
+    landing::Index,
+    login::{is_valid_email, is_valid_password},
+    password::hash_password,
+    roles::{Admin, Authorized, Manager, Role},
+    DB,
+};
+
+#[derive(Template)]
+#[template(path = "roles.html")]
+pub struct Roles {
+    staff: Vec<StaffAccount>,
+    can_edit: bool,
+    roles: [Role; 4],
+    email: String,
+    error: Option<&'static str>,
+    notice: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct StaffAccount {
+    email: String,
+    role: Role,
+}
+
+#[derive(Deserialize)]
+pub struct RoleForm {
+    email: String,
+    role: String,
+}
+
+// managers see who has which role, only admins can change them
+pub async fn roles_page(
+    Authorized(user, _): Authorized<Manager>,
+    CsrfToken(csrf): CsrfToken,
+    HxRequest(htmx): HxRequest,
+) -> Result<Response, AppError> {
+    if !htmx {
+        return Ok(Index {
+            logged_in: true,
+            content: "/admin/roles".to_string(),
+            csrf,
+        }
+        .into_response());
+    }
+    Ok(roles(user.role, String::new(), None, None)
+        .await?
+        .into_response())
+}
+
+// admins can't change their own role, so there is always one left
+pub async fn set_role(
+    Authorized(admin, _): Authorized<Admin>,
+    Form(RoleForm { email, role }): Form<RoleForm>,
+) -> Result<Roles, AppError> {
+    let email = email.trim().to_string();
+    let Some(role) = Role::from_name(&role) else {
+        return Err(AppError::BadRequest(format!("Unknown role {role}.")));
+    };
+    if !is_valid_email(&email) {
+        return roles(admin.role, email, Some("invalid email"), None).await;
+    }
+    if email == admin.email {
+        return roles(
+            admin.role,
+            email,
+            Some("You can't change your own role"),
+            None,
+        )
+        .await;
+    }
+
+    let mut query = DB
+        .query("UPDATE accounts SET role = $role WHERE email = $email RETURN VALUE id")
+        .bind(("role", role))
+        .bind(("email", &email))
+        .timed()
+        .await?;
+    let updated: Vec<Thing> = query.take(0)?;
+    if updated.is_empty() {
+        return roles(admin.role, email, Some("No account has that email"), None).await;
+    }
+    tracing::info!(
+        admin = %admin.account,
+        account = %updated[0],
+        role = role.name(),
+        "changed account role"
+    );
+    roles(
+        admin.role,
+        String::new(),
+        None,
+        Some(format!("{email} is now {role}.")),
+    )
+    .await
+}
+
+async fn roles(
+    role: Role,
+    email: String,
+    error: Option<&'static str>,
+    notice: Option<String>,
+) -> Result<Roles, AppError> {
+    let mut query = DB
+        .query(
+            r#"
+            SELECT email, role FROM accounts
+            WHERE role != "customer"
+            ORDER BY role, email
+            "#,
+        )
+        .timed()
+        .await?;
+    let staff = query.take(0)?;
+    Ok(Roles {
+        staff,
+        can_edit: role >= Role::Admin,
+        roles: Role::ALL,
+        email,
+        error,
+        notice,
+    })
+}
+
+// an existing account is promoted and keeps its password, otherwise a verified
+// account is created with the password from THEATER_ADMIN_PASSWORD or stdin
+pub async fn create_admin(email: &str) -> anyhow::Result<()> {
+    let email = email.trim().to_string();
+    if !is_valid_email(&email) {
+        bail!("invalid email {email}");
+    }
+
+    let mut query = DB
+        .query("UPDATE accounts SET role = $role WHERE email = $email RETURN VALUE id")
+        .bind(("role", Role::Admin))
+        .bind(("email", &email))
+        .await?;
+    let promoted: Vec<Thing> = query.take(0)?;
+    if let Some(account) = promoted.first() {
+        tracing::info!(%account, "promoted {email} to admin");
+        return Ok(());
+    }
+
+    let password = match env::var("THEATER_ADMIN_PASSWORD") {
+        Ok(password) => password,
+        Err(_) => {
+            eprintln!("password for {email}:");
+            let mut line = String::new();
+            io::stdin()
+                .read_line(&mut line)
+                .context("failed to read password from stdin")?;
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    if !is_valid_password(&password) {
+        bail!("the password must not be empty");
+    }
+    let hash = hash_password(password).await?;
+    let mut query = DB
+        .query(
+            r#"
+            CREATE ONLY accounts SET email = $email, password = $password,
+                verified_at = time::now(), role = $role
+            RETURN VALUE id;
+            "#,
+        )
+        .bind(("email", &email))
+        .bind(("password", hash))
+        .bind(("role", Role::Admin))
+        .await?;
+    let Some(account): Option<Thing> = query.take(0)? else {
+        bail!("admin account {email} not created");
+    };
+    tracing::info!(%account, "created admin {email}");
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, Request},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::cookie::{Key, PrivateCookieJar};
+use axum_htmx::HxRequest;
+use surrealdb::sql::Thing;
+use tracing::{field, Span};
+
+use crate::{error::AppError, roles::Role, session::validate_session};
+
+#[derive(Debug, Clone)]
+pub struct CurrentUser {
+    pub session: String,
+    pub account: Thing,
+    pub email: String,
+    pub verified: bool,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone)]
+pub struct OptionalUser(pub Option<CurrentUser>);
+
+// a logged in user whose email address has been verified
+#[derive(Debug, Clone)]
+pub struct VerifiedUser(pub CurrentUser);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CurrentUser
+where
+    S: Send + Sync,
+    Key: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // the guard layer and the handler share one lookup per request
+        if let Some(user) = parts.extensions.get::<CurrentUser>() {
+            return Ok(user.clone());
+        }
+
+        let jar = PrivateCookieJar::from_headers(&parts.headers, Key::from_ref(state));
+        let Some(session) = jar.get("session") else {
+            return Err(AppError::Unauthorized);
+        };
+        let Some(account) = validate_session(session.value()).await? else {
+            return Err(AppError::Unauthorized);
+        };
+
+        let user = CurrentUser {
+            session: session.value().to_string(),
+            account: account.id,
+            email: account.email,
+            verified: account.verified,
+            role: account.role,
+        };
+        Span::current().record("account", field::display(&user.account));
+        parts.extensions.insert(user.clone());
+        Ok(user)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for OptionalUser
+where
+    S: Send + Sync,
+    Key: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match CurrentUser::from_request_parts(parts, state).await {
+            Ok(user) => Ok(OptionalUser(Some(user))),
+            Err(AppError::Unauthorized) => Ok(OptionalUser(None)),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for VerifiedUser
+where
+    S: Send + Sync,
+    Key: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = CurrentUser::from_request_parts(parts, state).await?;
+        if !user.verified {
+            return Err(AppError::Forbidden(
+                "Please verify your email address before buying tickets. You can resend the link from your Tickets page."
+                    .to_string(),
+            ));
+        }
+        Ok(VerifiedUser(user))
+    }
+}
+
+// htmx requests get the 401 fragment, anything else is sent to the login page
+pub async fn require_user(
+    HxRequest(htmx): HxRequest,
+    user: Result<CurrentUser, AppError>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    match user {
+        Ok(_) => next.run(req).await,
+        Err(AppError::Unauthorized) if !htmx => Redirect::to("/login").into_response(),
+        Err(err) => err.into_response(),
+    }
+}
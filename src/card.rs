@@ -0,0 +1,299 @@
+use std::fmt;
+
+use chrono::{Datelike, NaiveDate};
+
+// cards expiring further out than this are typos
+const MAX_YEARS_AHEAD: i32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Brand {
+    Visa,
+    Mastercard,
+    Amex,
+    Discover,
+    DinersClub,
+    Jcb,
+}
+
+impl Brand {
+    pub const ALL: [Brand; 6] = [
+        Brand::Visa,
+        Brand::Mastercard,
+        Brand::Amex,
+        Brand::Discover,
+        Brand::DinersClub,
+        Brand::Jcb,
+    ];
+
+    // from the leading digits of the number, its issuer identification number
+    pub fn detect(digits: &str) -> Option<Brand> {
+        let prefix = |len: usize| digits.get(..len).and_then(|p| p.parse::<u32>().ok());
+        match (prefix(1), prefix(2), prefix(3), prefix(4), prefix(6)) {
+            (Some(4), ..) => Some(Brand::Visa),
+            (_, Some(34 | 37), ..) => Some(Brand::Amex),
+            (_, Some(51..=55), ..) | (_, _, _, Some(2221..=2720), _) => Some(Brand::Mastercard),
+            (_, Some(65), ..)
+            | (_, _, Some(644..=649), ..)
+            | (_, _, _, Some(6011), _)
+            | (.., Some(622126..=622925)) => Some(Brand::Discover),
+            (_, Some(36 | 38 | 39), ..) | (_, _, Some(300..=305 | 309), ..) => {
+                Some(Brand::DinersClub)
+            }
+            (_, _, _, Some(3528..=3589), _) => Some(Brand::Jcb),
+            _ => None,
+        }
+    }
+
+    pub fn lengths(self) -> &'static [usize] {
+        match self {
+            Brand::Visa => &[13, 16, 19],
+            Brand::Mastercard => &[16],
+            Brand::Amex => &[15],
+            Brand::Discover => &[16, 19],
+            Brand::DinersClub => &[14, 16, 19],
+            Brand::Jcb => &[16, 17, 18, 19],
+        }
+    }
+
+    pub fn cvv_length(self) -> usize {
+        match self {
+            Brand::Amex => 4,
+            _ => 3,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Brand::Visa => "visa",
+            Brand::Mastercard => "mastercard",
+            Brand::Amex => "amex",
+            Brand::Discover => "discover",
+            Brand::DinersClub => "diners",
+            Brand::Jcb => "jcb",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Brand> {
+        Brand::ALL.into_iter().find(|brand| brand.name() == name)
+    }
+}
+
+impl fmt::Display for Brand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Brand::Visa => "Visa",
+            Brand::Mastercard => "Mastercard",
+            Brand::Amex => "American Express",
+            Brand::Discover => "Discover",
+            Brand::DinersClub => "Diners Club",
+            Brand::Jcb => "JCB",
+        };
+        f.write_str(name)
+    }
+}
+
+// the digits of a number typed with or without spaces and dashes
+pub fn digits(input: &str) -> Option<String> {
+    let mut digits = String::new();
+    for ch in input.trim().chars() {
+        match ch {
+            '0'..='9' => digits.push(ch),
+            ' ' | '-' => {}
+            _ => return None,
+        }
+    }
+    Some(digits)
+}
+
+pub fn luhn(digits: &str) -> bool {
+    let mut sum = 0;
+    for (i, ch) in digits.chars().rev().enumerate() {
+        let Some(mut digit) = ch.to_digit(10) else {
+            return false;
+        };
+        if i % 2 == 1 {
+            digit *= 2;
+            if digit > 9 {
+                digit -= 9;
+            }
+        }
+        sum += digit;
+    }
+    !digits.is_empty() && sum % 10 == 0
+}
+
+// the card's digits and brand if the number could be a real card
+pub fn card_number(input: &str) -> Option<(String, Brand)> {
+    let digits = digits(input)?;
+    let brand = Brand::detect(&digits)?;
+    if !brand.lengths().contains(&digits.len()) || !luhn(&digits) {
+        return None;
+    }
+    Some((digits, brand))
+}
+
+// without a known brand any three or four digits will do
+pub fn is_valid_cvv(cvv: &str, brand: Option<Brand>) -> bool {
+    let cvv = cvv.trim();
+    let length_ok = match brand {
+        Some(brand) => cvv.len() == brand.cvv_length(),
+        None => cvv.len() == 3 || cvv.len() == 4,
+    };
+    length_ok && cvv.chars().all(|ch| ch.is_ascii_digit())
+}
+
+// MM/YY or MM/YYYY, a card is good through the last day of its month
+pub fn is_valid_expiry(exp: &str, today: NaiveDate) -> bool {
+    let Some((month, year)) = exp.trim().split_once('/') else {
+        return false;
+    };
+    let (month, year) = (month.trim(), year.trim());
+    if month.len() != 2 || !(year.len() == 2 || year.len() == 4) {
+        return false;
+    }
+    if !month
+        .chars()
+        .chain(year.chars())
+        .all(|ch| ch.is_ascii_digit())
+    {
+        return false;
+    }
+    let (Ok(month), Ok(mut year)) = (month.parse::<u32>(), year.parse::<i32>()) else {
+        return false;
+    };
+    if !(1..=12).contains(&month) {
+        return false;
+    }
+    if year < 100 {
+        year += 2000;
+    }
+    let expires = (year, month);
+    let current = (today.year(), today.month());
+    expires >= current && year <= today.year() + MAX_YEARS_AHEAD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // pads an IIN to the brand's length and fixes the check digit
+    fn number(prefix: &str, len: usize) -> String {
+        let mut body = prefix.to_string();
+        while body.len() < len - 1 {
+            body.push('0');
+        }
+        let check = (0..10)
+            .find(|check| luhn(&format!("{body}{check}")))
+            .unwrap();
+        format!("{body}{check}")
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn luhn_checks_the_check_digit() {
+        assert!(luhn("4242424242424242"));
+        assert!(luhn("378282246310005"));
+        assert!(!luhn("4242424242424241"));
+        assert!(!luhn(""));
+        assert!(!luhn("4242x42424242424"));
+    }
+
+    #[test]
+    fn detects_brands_at_the_edges_of_their_ranges() {
+        let cases = [
+            ("4", Some(Brand::Visa)),
+            ("34", Some(Brand::Amex)),
+            ("37", Some(Brand::Amex)),
+            ("51", Some(Brand::Mastercard)),
+            ("55", Some(Brand::Mastercard)),
+            ("56", None),
+            ("2220", None),
+            ("2221", Some(Brand::Mastercard)),
+            ("2720", Some(Brand::Mastercard)),
+            ("2721", None),
+            ("6011", Some(Brand::Discover)),
+            ("622125", None),
+            ("622126", Some(Brand::Discover)),
+            ("622925", Some(Brand::Discover)),
+            ("622926", None),
+            ("3527", None),
+            ("3528", Some(Brand::Jcb)),
+            ("3589", Some(Brand::Jcb)),
+            ("3590", None),
+        ];
+        for (prefix, brand) in cases {
+            assert_eq!(Brand::detect(&number(prefix, 16)), brand, "{prefix}");
+        }
+    }
+
+    #[test]
+    fn card_numbers_need_the_brand_length() {
+        assert_eq!(
+            card_number(&number("4", 16)).map(|(_, brand)| brand),
+            Some(Brand::Visa)
+        );
+        assert_eq!(
+            card_number(&number("4", 13)).map(|(_, brand)| brand),
+            Some(Brand::Visa)
+        );
+        assert!(card_number(&number("4", 15)).is_none());
+        assert!(card_number(&number("34", 15)).is_some());
+        assert!(card_number(&number("34", 16)).is_none());
+        assert!(card_number(&number("51", 16)).is_some());
+        assert!(card_number(&number("51", 19)).is_none());
+        assert!(card_number(&number("3528", 19)).is_some());
+        assert!(card_number(&number("3528", 15)).is_none());
+    }
+
+    #[test]
+    fn card_numbers_allow_spaces_and_dashes() {
+        let expected = Some(("4242424242424242".to_string(), Brand::Visa));
+        assert_eq!(card_number("4242 4242 4242 4242"), expected);
+        assert_eq!(card_number("4242-4242-4242-4242"), expected);
+        assert_eq!(card_number(" 4242424242424242 "), expected);
+        assert!(card_number("4242.4242.4242.4242").is_none());
+        assert!(card_number("4242424242424241").is_none());
+    }
+
+    #[test]
+    fn amex_cvvs_have_four_digits() {
+        assert!(is_valid_cvv("1234", Some(Brand::Amex)));
+        assert!(!is_valid_cvv("123", Some(Brand::Amex)));
+        assert!(is_valid_cvv("123", Some(Brand::Visa)));
+        assert!(!is_valid_cvv("1234", Some(Brand::Visa)));
+        assert!(is_valid_cvv("123", None));
+        assert!(is_valid_cvv("1234", None));
+        assert!(!is_valid_cvv("12a", None));
+    }
+
+    #[test]
+    fn cards_are_good_through_their_month() {
+        let today = date(2026, 1, 31);
+        assert!(is_valid_expiry("01/26", today));
+        assert!(is_valid_expiry("01/2026", today));
+        assert!(is_valid_expiry("02/26", today));
+        assert!(!is_valid_expiry("12/25", today));
+        assert!(!is_valid_expiry("12/2025", today));
+    }
+
+    #[test]
+    fn rejects_malformed_and_distant_expiries() {
+        let today = date(2026, 6, 15);
+        assert!(!is_valid_expiry("13/26", today));
+        assert!(!is_valid_expiry("00/27", today));
+        assert!(!is_valid_expiry("6/27", today));
+        assert!(!is_valid_expiry("0627", today));
+        assert!(!is_valid_expiry("06/2", today));
+        assert!(is_valid_expiry(
+            &format!("06/{}", 2026 + MAX_YEARS_AHEAD),
+            today
+        ));
+        assert!(!is_valid_expiry(
+            &format!("07/{}", 2027 + MAX_YEARS_AHEAD),
+            today
+        ));
+    }
+}
@@ -0,0 +1,32 @@
+use std::{env, path::PathBuf};
+
+use anyhow::bail;
+
+const USAGE: &str =
+    "usage: axum_movie_theater_server [serve | migrate | import [DATA_DIR] | create-admin EMAIL]";
+
+pub enum Command {
+    Serve,
+    Migrate,
+    Import(PathBuf),
+    CreateAdmin(String),
+}
+
+impl Command {
+    pub fn from_args() -> anyhow::Result<Command> {
+        let args: Vec<String> = env::args().skip(1).collect();
+        match args
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            [] | ["serve"] => Ok(Command::Serve),
+            ["migrate"] => Ok(Command::Migrate),
+            ["import"] => Ok(Command::Import(PathBuf::from("data"))),
+            ["import", dir] => Ok(Command::Import(PathBuf::from(dir))),
+            ["create-admin", email] => Ok(Command::CreateAdmin(email.to_string())),
+            _ => bail!("{USAGE}"),
+        }
+    }
+}
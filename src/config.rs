@@ -0,0 +1,569 @@
+use std::{collections::HashMap, env, fs, net::SocketAddr, path::PathBuf};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+const CONFIG_PATH: &str = "config.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub cookies: CookieConfig,
+    pub logging: LoggingConfig,
+    pub pricing: PricingConfig,
+    pub seating: SeatingConfig,
+    pub payments: PaymentConfig,
+    pub cancellation: CancellationConfig,
+    pub mail: MailConfig,
+    pub accounts: AccountsConfig,
+    pub limits: LimitsConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub addr: SocketAddr,
+    pub public_url: String,
+    pub trust_forwarded_for: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub engine: DatabaseEngine,
+    pub address: String,
+    pub path: PathBuf,
+    pub auth: DatabaseAuth,
+    pub username: String,
+    pub password: String,
+    pub namespace: String,
+    pub database: String,
+    pub auto_migrate: bool,
+    pub slow_query_ms: u64,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseEngine {
+    Remote,
+    Memory,
+    RocksDb,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseAuth {
+    Root,
+    Namespace,
+    Database,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CookieConfig {
+    pub key: Option<String>,
+    pub key_file: Option<PathBuf>,
+    pub previous_keys: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    pub level: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+// prices are in cents
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PricingConfig {
+    pub adult: u32,
+    pub child: u32,
+    pub senior: u32,
+    pub student: u32,
+    pub matinee_before: u32,
+    pub matinee_discount: u32,
+    pub weekend_surcharge: u32,
+    pub formats: HashMap<String, u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SeatingConfig {
+    pub hold_minutes: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub login_free_attempts: u32,
+    pub login_backoff_seconds: u64,
+    pub login_max_backoff_seconds: u64,
+    pub login_lockout_after: u32,
+    pub login_lockout_minutes: u64,
+    pub requests_per_minute: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountsConfig {
+    pub reset_minutes: u32,
+    pub verification_hours: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    pub transport: MailTransport,
+    pub from: String,
+    pub dir: PathBuf,
+    pub smtp_host: String,
+    pub smtp_port: Option<u16>,
+    pub smtp_tls: SmtpTls,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    Smtp,
+    File,
+    Stub,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    StartTls,
+    Tls,
+    None,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CancellationConfig {
+    pub cutoff_minutes: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PaymentConfig {
+    pub provider: PaymentProviderKind,
+    pub timeout_ms: u64,
+    pub mock: MockPaymentConfig,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PaymentProviderKind {
+    Mock,
+}
+
+// cards are compared by their digits only
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MockPaymentConfig {
+    pub outcome: MockOutcome,
+    pub latency_ms: u64,
+    pub decline_cards: Vec<String>,
+    pub timeout_cards: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MockOutcome {
+    Approve,
+    Decline,
+    Timeout,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            addr: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            public_url: "http://127.0.0.1:8080".to_string(),
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            engine: DatabaseEngine::Remote,
+            address: "127.0.0.1:8000".to_string(),
+            path: PathBuf::from("theater.db"),
+            auth: DatabaseAuth::Root,
+            username: "root".to_string(),
+            password: "root".to_string(),
+            namespace: "theater".to_string(),
+            database: "theater".to_string(),
+            auto_migrate: true,
+            slow_query_ms: 250,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::Pretty,
+            level: "info".to_string(),
+        }
+    }
+}
+
+impl Default for PricingConfig {
+    fn default() -> Self {
+        PricingConfig {
+            adult: 1200,
+            child: 800,
+            senior: 900,
+            student: 1000,
+            matinee_before: 17,
+            matinee_discount: 300,
+            weekend_surcharge: 150,
+            formats: HashMap::from([
+                ("standard".to_string(), 0),
+                ("3d".to_string(), 300),
+                ("imax".to_string(), 500),
+            ]),
+        }
+    }
+}
+
+impl Default for SeatingConfig {
+    fn default() -> Self {
+        SeatingConfig { hold_minutes: 10 }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            login_free_attempts: 3,
+            login_backoff_seconds: 2,
+            login_max_backoff_seconds: 300,
+            login_lockout_after: 10,
+            login_lockout_minutes: 15,
+            requests_per_minute: 20,
+        }
+    }
+}
+
+impl Default for AccountsConfig {
+    fn default() -> Self {
+        AccountsConfig {
+            reset_minutes: 30,
+            verification_hours: 24,
+        }
+    }
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            transport: MailTransport::Stub,
+            from: "Movie Theater <tickets@localhost>".to_string(),
+            dir: PathBuf::from("mail"),
+            smtp_host: "localhost".to_string(),
+            smtp_port: None,
+            smtp_tls: SmtpTls::StartTls,
+            smtp_username: None,
+            smtp_password: None,
+        }
+    }
+}
+
+impl Default for CancellationConfig {
+    fn default() -> Self {
+        CancellationConfig { cutoff_minutes: 60 }
+    }
+}
+
+impl Default for PaymentConfig {
+    fn default() -> Self {
+        PaymentConfig {
+            provider: PaymentProviderKind::Mock,
+            timeout_ms: 10_000,
+            mock: MockPaymentConfig::default(),
+        }
+    }
+}
+
+impl Default for MockPaymentConfig {
+    fn default() -> Self {
+        MockPaymentConfig {
+            outcome: MockOutcome::Approve,
+            latency_ms: 200,
+            decline_cards: vec!["4000000000000002".to_string()],
+            timeout_cards: vec!["4000000000000119".to_string()],
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Config> {
+        let mut config = match env::var("THEATER_CONFIG") {
+            Ok(path) => Config::from_file(&path)?,
+            Err(_) if fs::metadata(CONFIG_PATH).is_ok() => Config::from_file(CONFIG_PATH)?,
+            Err(_) => Config::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &str) -> anyhow::Result<Config> {
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("failed to read config {path}"))?;
+        toml::from_str(&contents).with_context(|| format!("invalid config {path}"))
+    }
+
+    fn apply_env(&mut self) -> anyhow::Result<()> {
+        if let Ok(addr) = env::var("THEATER_ADDR") {
+            self.server.addr = addr
+                .parse()
+                .with_context(|| format!("THEATER_ADDR is not a socket address: {addr}"))?;
+        }
+        override_string("THEATER_PUBLIC_URL", &mut self.server.public_url);
+        if let Ok(trust) = env::var("THEATER_TRUST_FORWARDED_FOR") {
+            self.server.trust_forwarded_for = trust.parse().with_context(|| {
+                format!("THEATER_TRUST_FORWARDED_FOR must be true or false: {trust}")
+            })?;
+        }
+        if let Ok(engine) = env::var("THEATER_DB_ENGINE") {
+            self.database.engine = match engine.as_str() {
+                "remote" => DatabaseEngine::Remote,
+                "memory" => DatabaseEngine::Memory,
+                "rocksdb" => DatabaseEngine::RocksDb,
+                _ => bail!("THEATER_DB_ENGINE must be remote, memory or rocksdb: {engine}"),
+            };
+        }
+        if let Ok(path) = env::var("THEATER_DB_PATH") {
+            self.database.path = path.into();
+        }
+        if let Ok(auth) = env::var("THEATER_DB_AUTH") {
+            self.database.auth = match auth.as_str() {
+                "root" => DatabaseAuth::Root,
+                "namespace" => DatabaseAuth::Namespace,
+                "database" => DatabaseAuth::Database,
+                _ => bail!("THEATER_DB_AUTH must be root, namespace or database: {auth}"),
+            };
+        }
+        override_string("THEATER_DB_ADDR", &mut self.database.address);
+        override_string("THEATER_DB_USER", &mut self.database.username);
+        override_string("THEATER_DB_PASS", &mut self.database.password);
+        override_string("THEATER_DB_NAMESPACE", &mut self.database.namespace);
+        override_string("THEATER_DB_DATABASE", &mut self.database.database);
+        if let Ok(migrate) = env::var("THEATER_DB_AUTO_MIGRATE") {
+            self.database.auto_migrate = migrate
+                .parse()
+                .with_context(|| format!("THEATER_DB_AUTO_MIGRATE must be true or false: {migrate}"))?;
+        }
+        if let Ok(slow) = env::var("THEATER_DB_SLOW_QUERY_MS") {
+            self.database.slow_query_ms = slow.parse().with_context(|| {
+                format!("THEATER_DB_SLOW_QUERY_MS must be a number of milliseconds: {slow}")
+            })?;
+        }
+
+        if let Ok(key) = env::var("THEATER_COOKIE_KEY") {
+            self.cookies.key = Some(key);
+            self.cookies.key_file = None;
+        } else if let Ok(path) = env::var("THEATER_COOKIE_KEY_FILE") {
+            self.cookies.key = None;
+            self.cookies.key_file = Some(path.into());
+        }
+        if let Ok(keys) = env::var("THEATER_PREVIOUS_COOKIE_KEYS") {
+            self.cookies.previous_keys = keys
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(String::from)
+                .collect();
+        }
+
+        if let Ok(format) = env::var("THEATER_LOG_FORMAT") {
+            self.logging.format = match format.as_str() {
+                "pretty" => LogFormat::Pretty,
+                "json" => LogFormat::Json,
+                _ => bail!("THEATER_LOG_FORMAT must be pretty or json: {format}"),
+            };
+        }
+        override_string("THEATER_LOG_LEVEL", &mut self.logging.level);
+
+        if let Ok(minutes) = env::var("THEATER_SEAT_HOLD_MINUTES") {
+            self.seating.hold_minutes = minutes.parse().with_context(|| {
+                format!("THEATER_SEAT_HOLD_MINUTES must be a number of minutes: {minutes}")
+            })?;
+        }
+        if let Ok(minutes) = env::var("THEATER_CANCEL_CUTOFF_MINUTES") {
+            self.cancellation.cutoff_minutes = minutes.parse().with_context(|| {
+                format!("THEATER_CANCEL_CUTOFF_MINUTES must be a number of minutes: {minutes}")
+            })?;
+        }
+
+        if let Ok(transport) = env::var("THEATER_MAIL_TRANSPORT") {
+            self.mail.transport = match transport.as_str() {
+                "smtp" => MailTransport::Smtp,
+                "file" => MailTransport::File,
+                "stub" => MailTransport::Stub,
+                _ => bail!("THEATER_MAIL_TRANSPORT must be smtp, file or stub: {transport}"),
+            };
+        }
+        override_string("THEATER_MAIL_FROM", &mut self.mail.from);
+        if let Ok(dir) = env::var("THEATER_MAIL_DIR") {
+            self.mail.dir = dir.into();
+        }
+        override_string("THEATER_SMTP_HOST", &mut self.mail.smtp_host);
+        if let Ok(port) = env::var("THEATER_SMTP_PORT") {
+            self.mail.smtp_port = Some(
+                port.parse()
+                    .with_context(|| format!("THEATER_SMTP_PORT is not a port: {port}"))?,
+            );
+        }
+        if let Ok(username) = env::var("THEATER_SMTP_USER") {
+            self.mail.smtp_username = Some(username);
+        }
+        if let Ok(password) = env::var("THEATER_SMTP_PASS") {
+            self.mail.smtp_password = Some(password);
+        }
+
+        if let Ok(minutes) = env::var("THEATER_RESET_MINUTES") {
+            self.accounts.reset_minutes = minutes.parse().with_context(|| {
+                format!("THEATER_RESET_MINUTES must be a number of minutes: {minutes}")
+            })?;
+        }
+        if let Ok(hours) = env::var("THEATER_VERIFICATION_HOURS") {
+            self.accounts.verification_hours = hours.parse().with_context(|| {
+                format!("THEATER_VERIFICATION_HOURS must be a number of hours: {hours}")
+            })?;
+        }
+
+        if let Ok(limit) = env::var("THEATER_REQUESTS_PER_MINUTE") {
+            self.limits.requests_per_minute = limit.parse().with_context(|| {
+                format!("THEATER_REQUESTS_PER_MINUTE must be a number: {limit}")
+            })?;
+        }
+
+        if let Ok(timeout) = env::var("THEATER_PAYMENT_TIMEOUT_MS") {
+            self.payments.timeout_ms = timeout.parse().with_context(|| {
+                format!("THEATER_PAYMENT_TIMEOUT_MS must be a number of milliseconds: {timeout}")
+            })?;
+        }
+        if let Ok(outcome) = env::var("THEATER_MOCK_PAYMENT_OUTCOME") {
+            self.payments.mock.outcome = match outcome.as_str() {
+                "approve" => MockOutcome::Approve,
+                "decline" => MockOutcome::Decline,
+                "timeout" => MockOutcome::Timeout,
+                _ => bail!(
+                    "THEATER_MOCK_PAYMENT_OUTCOME must be approve, decline or timeout: {outcome}"
+                ),
+            };
+        }
+        if let Ok(latency) = env::var("THEATER_MOCK_PAYMENT_LATENCY_MS") {
+            self.payments.mock.latency_ms = latency.parse().with_context(|| {
+                format!(
+                    "THEATER_MOCK_PAYMENT_LATENCY_MS must be a number of milliseconds: {latency}"
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+        if self.database.engine == DatabaseEngine::Remote
+            && self.database.address.trim().is_empty()
+        {
+            errors.push("database.address must not be empty");
+        }
+        if self.database.engine == DatabaseEngine::RocksDb
+            && self.database.path.as_os_str().is_empty()
+        {
+            errors.push("database.path must not be empty");
+        }
+        if self.database.engine == DatabaseEngine::Remote && self.database.username.is_empty() {
+            errors.push("database.username must not be empty");
+        }
+        if self.database.namespace.is_empty() {
+            errors.push("database.namespace must not be empty");
+        }
+        if self.database.database.is_empty() {
+            errors.push("database.database must not be empty");
+        }
+        if self.cookies.key.is_some() && self.cookies.key_file.is_some() {
+            errors.push("only one of cookies.key and cookies.key_file may be set");
+        }
+        if self.seating.hold_minutes == 0 {
+            errors.push("seating.hold_minutes must be at least 1");
+        }
+        if self.pricing.matinee_before > 24 {
+            errors.push("pricing.matinee_before must be an hour between 0 and 24");
+        }
+        let cheapest = [
+            self.pricing.adult,
+            self.pricing.child,
+            self.pricing.senior,
+            self.pricing.student,
+        ]
+        .into_iter()
+        .min()
+        .unwrap_or(0);
+        if self.pricing.matinee_discount > cheapest {
+            errors.push("pricing.matinee_discount must not exceed the cheapest ticket");
+        }
+        if !self.server.public_url.starts_with("http://")
+            && !self.server.public_url.starts_with("https://")
+        {
+            errors.push("server.public_url must start with http:// or https://");
+        }
+        if self.accounts.reset_minutes == 0 {
+            errors.push("accounts.reset_minutes must be at least 1");
+        }
+        if self.accounts.verification_hours == 0 {
+            errors.push("accounts.verification_hours must be at least 1");
+        }
+        if self.limits.login_lockout_after <= self.limits.login_free_attempts {
+            errors.push("limits.login_lockout_after must be more than limits.login_free_attempts");
+        }
+        if self.limits.requests_per_minute == 0 {
+            errors.push("limits.requests_per_minute must be at least 1");
+        }
+        if self.mail.from.parse::<lettre::message::Mailbox>().is_err() {
+            errors.push("mail.from must be an email address like \"Name <user@host>\"");
+        }
+        if self.mail.transport == MailTransport::Smtp && self.mail.smtp_host.is_empty() {
+            errors.push("mail.smtp_host must not be empty");
+        }
+        if self.mail.transport == MailTransport::File && self.mail.dir.as_os_str().is_empty() {
+            errors.push("mail.dir must not be empty");
+        }
+        if self.mail.smtp_username.is_some() != self.mail.smtp_password.is_some() {
+            errors.push("mail.smtp_username and mail.smtp_password must be set together");
+        }
+        if self.payments.timeout_ms == 0 {
+            errors.push("payments.timeout_ms must be at least 1");
+        }
+        if !errors.is_empty() {
+            bail!("invalid configuration:\n  {}", errors.join("\n  "));
+        }
+        Ok(())
+    }
+}
+
+fn override_string(var: &str, value: &mut String) {
+    if let Ok(new) = env::var(var) {
+        *value = new;
+    }
+}
@@ -0,0 +1,226 @@
+use std::sync::Arc;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{FromRequestParts, State},
+    http::{
+        header::{HOST, ORIGIN, REFERER, SET_COOKIE},
+        request::Parts,
+        HeaderMap, Method, Request,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::{Cookie, Key, PrivateCookieJar, SameSite};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+use crate::{config::Config, error::AppError};
+
+const COOKIE_NAME: &str = "csrf";
+const HEADER_NAME: &str = "X-CSRF-Token";
+
+// the token for this request's session, index.html hands it to htmx so every
+// request it makes carries it back in the X-CSRF-Token header
+#[derive(Debug, Clone)]
+pub struct CsrfToken(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CsrfToken
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<CsrfToken>() {
+            Some(token) => Ok(token.clone()),
+            None => Err(AppError::Internal(anyhow::anyhow!(
+                "csrf layer is not installed"
+            ))),
+        }
+    }
+}
+
+// a fresh token in an encrypted cookie, given out on the first visit and
+// again whenever a session starts
+pub fn csrf_cookie() -> Cookie<'static> {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    Cookie::build(COOKIE_NAME, URL_SAFE_NO_PAD.encode(bytes))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+// anything but a read has to come from our own pages and carry the token of
+// the browser's cookie, which another site can neither read nor set
+pub async fn verify_csrf(
+    State(key): State<Key>,
+    State(config): State<Arc<Config>>,
+    mut req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let jar = PrivateCookieJar::from_headers(req.headers(), key);
+    let existing = jar
+        .get(COOKIE_NAME)
+        .map(|cookie| cookie.value().to_string());
+
+    if !is_safe(req.method()) {
+        if !same_origin(req.headers(), &config.server.public_url) {
+            tracing::warn!(
+                "rejected cross-origin {} {}",
+                req.method(),
+                req.uri().path()
+            );
+            return forbidden().into_response();
+        }
+        let sent = req
+            .headers()
+            .get(HEADER_NAME)
+            .and_then(|value| value.to_str().ok());
+        if !valid_token(existing.as_deref(), sent) {
+            tracing::warn!(
+                "rejected {} {} without a valid csrf token",
+                req.method(),
+                req.uri().path()
+            );
+            return forbidden().into_response();
+        }
+    }
+
+    let (token, issued) = match existing {
+        Some(token) => (token, None),
+        None => {
+            let cookie = csrf_cookie();
+            (cookie.value().to_string(), Some(jar.add(cookie)))
+        }
+    };
+    req.extensions_mut().insert(CsrfToken(token));
+
+    let mut response = next.run(req).await;
+    let Some(jar) = issued else {
+        return response;
+    };
+    // a handler that started a session already set its own token
+    let overwritten = response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.starts_with(&format!("{COOKIE_NAME}=")));
+    if !overwritten {
+        for value in jar.into_response().headers().get_all(SET_COOKIE) {
+            response.headers_mut().append(SET_COOKIE, value.clone());
+        }
+    }
+    response
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+// browsers send Origin on every POST, or at least a Referer, and it has to
+// name either the public url or the host the request was sent to. without
+// either the token alone decides
+fn same_origin(headers: &HeaderMap, public_url: &str) -> bool {
+    let Some(origin) = headers.get(ORIGIN).or_else(|| headers.get(REFERER)) else {
+        return true;
+    };
+    let Some(origin) = origin.to_str().ok().and_then(host_of) else {
+        return false;
+    };
+    let host = headers.get(HOST).and_then(|value| value.to_str().ok());
+    host_of(public_url) == Some(origin) || host == Some(origin)
+}
+
+fn host_of(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("://")?;
+    rest.split('/').next().filter(|host| !host.is_empty())
+}
+
+fn valid_token(expected: Option<&str>, sent: Option<&str>) -> bool {
+    match (expected, sent) {
+        (Some(expected), Some(sent)) => !expected.is_empty() && constant_time_eq(expected, sent),
+        _ => false,
+    }
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn forbidden() -> AppError {
+    AppError::Forbidden(
+        "This request didn't come from our site. Please reload the page and try again.".to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    const PUBLIC_URL: &str = "https://theater.example.com";
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn accepts_our_own_origin() {
+        let public = headers(&[("origin", "https://theater.example.com")]);
+        assert!(same_origin(&public, PUBLIC_URL));
+        let host = headers(&[
+            ("origin", "http://localhost:3000"),
+            ("host", "localhost:3000"),
+        ]);
+        assert!(same_origin(&host, PUBLIC_URL));
+    }
+
+    #[test]
+    fn rejects_another_origin() {
+        let other = headers(&[
+            ("origin", "https://evil.example.com"),
+            ("host", "localhost:3000"),
+        ]);
+        assert!(!same_origin(&other, PUBLIC_URL));
+        assert!(!same_origin(&headers(&[("origin", "null")]), PUBLIC_URL));
+    }
+
+    #[test]
+    fn falls_back_to_the_referer() {
+        let ours = headers(&[("referer", "https://theater.example.com/purchase/1")]);
+        assert!(same_origin(&ours, PUBLIC_URL));
+        let other = headers(&[("referer", "https://evil.example.com/theater.example.com")]);
+        assert!(!same_origin(&other, PUBLIC_URL));
+    }
+
+    #[test]
+    fn leaves_requests_without_either_to_the_token() {
+        assert!(same_origin(&HeaderMap::new(), PUBLIC_URL));
+    }
+
+    #[test]
+    fn compares_tokens() {
+        assert!(valid_token(Some("abc123"), Some("abc123")));
+        assert!(!valid_token(Some("abc123"), Some("abc124")));
+        assert!(!valid_token(Some("abc123"), Some("abc1234")));
+        assert!(!valid_token(Some("abc123"), Some("")));
+        assert!(!valid_token(Some(""), Some("")));
+        assert!(!valid_token(Some("abc123"), None));
+        assert!(!valid_token(None, Some("abc123")));
+    }
+}
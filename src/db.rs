@@ -0,0 +1,113 @@
+use std::{
+    future::{Future, IntoFuture},
+    panic::Location,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+use once_cell::sync::{Lazy, OnceCell};
+use surrealdb::{
+    engine::any::Any,
+    opt::auth::{Database, Namespace, Root},
+    Surreal,
+};
+
+use crate::config::{DatabaseAuth, DatabaseConfig, DatabaseEngine};
+
+pub static DB: Lazy<Surreal<Any>> = Lazy::new(Surreal::init);
+
+static SLOW_QUERY: OnceCell<Duration> = OnceCell::new();
+
+pub async fn connect(config: &DatabaseConfig) -> anyhow::Result<()> {
+    let endpoint = match config.engine {
+        DatabaseEngine::Remote => format!("ws://{}", config.address),
+        DatabaseEngine::Memory => "mem://".to_string(),
+        DatabaseEngine::RocksDb if cfg!(feature = "rocksdb") => {
+            format!("rocksdb://{}", config.path.display())
+        }
+        DatabaseEngine::RocksDb => {
+            bail!("database.engine is rocksdb but the server was built without the rocksdb feature")
+        }
+    };
+    DB.connect(endpoint).await?;
+    let _ = SLOW_QUERY.set(Duration::from_millis(config.slow_query_ms));
+
+    // the embedded engines run without authentication
+    if config.engine == DatabaseEngine::Remote {
+        signin(config).await?;
+    }
+    DB.use_ns(&config.namespace)
+        .use_db(&config.database)
+        .await?;
+    Ok(())
+}
+
+async fn signin(config: &DatabaseConfig) -> surrealdb::Result<()> {
+    match config.auth {
+        DatabaseAuth::Root => {
+            DB.signin(Root {
+                username: &config.username,
+                password: &config.password,
+            })
+            .await?;
+        }
+        DatabaseAuth::Namespace => {
+            DB.signin(Namespace {
+                namespace: &config.namespace,
+                username: &config.username,
+                password: &config.password,
+            })
+            .await?;
+        }
+        DatabaseAuth::Database => {
+            DB.signin(Database {
+                namespace: &config.namespace,
+                database: &config.database,
+                username: &config.username,
+                password: &config.password,
+            })
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+// queries slower than database.slow_query_ms are logged with the location
+// they were awaited from
+pub trait Timed: IntoFuture + Sized {
+    #[track_caller]
+    fn timed(self) -> TimedQuery<Self::IntoFuture> {
+        TimedQuery {
+            query: Box::pin(self.into_future()),
+            location: Location::caller(),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl<Q: IntoFuture> Timed for Q {}
+
+pub struct TimedQuery<F> {
+    query: Pin<Box<F>>,
+    location: &'static Location<'static>,
+    start: Instant,
+}
+
+impl<F: Future> Future for TimedQuery<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let output = ready!(self.query.as_mut().poll(cx));
+        let elapsed = self.start.elapsed();
+        if SLOW_QUERY.get().is_some_and(|slow| elapsed >= *slow) {
+            tracing::warn!(
+                location = %self.location,
+                elapsed_ms = elapsed.as_millis() as u64,
+                "slow query"
+            );
+        }
+        Poll::Ready(output)
+    }
+}
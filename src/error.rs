@@ -0,0 +1,130 @@
+use askama::Template;
+use axum::{
+    body::Body,
+    http::{header::CONTENT_TYPE, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+#[derive(Template)]
+#[template(path = "error.html")]
+pub struct ErrorFragment {
+    title: String,
+    message: String,
+    login: bool,
+}
+
+#[derive(Template)]
+#[template(path = "error_page.html")]
+pub struct ErrorPage {
+    title: String,
+    message: String,
+    login: bool,
+}
+
+#[derive(Debug)]
+pub enum AppError {
+    NotFound,
+    BadRequest(String),
+    Unauthorized,
+    Forbidden(String),
+    Conflict(String),
+    TooManyRequests(String),
+    Unavailable(String),
+    Internal(anyhow::Error),
+}
+
+impl From<surrealdb::Error> for AppError {
+    fn from(err: surrealdb::Error) -> Self {
+        AppError::Internal(err.into())
+    }
+}
+
+impl From<askama::Error> for AppError {
+    fn from(err: askama::Error) -> Self {
+        AppError::Internal(err.into())
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(err: anyhow::Error) -> Self {
+        AppError::Internal(err)
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, title, message) = match self {
+            AppError::NotFound => (
+                StatusCode::NOT_FOUND,
+                "Not Found",
+                "We couldn't find what you were looking for.".to_string(),
+            ),
+            AppError::BadRequest(message) => (StatusCode::BAD_REQUEST, "Bad Request", message),
+            AppError::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                "Log In Required",
+                "You need to log in to do that.".to_string(),
+            ),
+            AppError::Forbidden(message) => (StatusCode::FORBIDDEN, "Not Allowed", message),
+            AppError::Conflict(message) => (StatusCode::CONFLICT, "Unavailable", message),
+            AppError::TooManyRequests(message) => {
+                (StatusCode::TOO_MANY_REQUESTS, "Slow Down", message)
+            }
+            AppError::Unavailable(message) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "Try Again Later", message)
+            }
+            AppError::Internal(err) => {
+                tracing::error!("internal error: {err:#}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Something Went Wrong",
+                    "Please try again in a moment.".to_string(),
+                )
+            }
+        };
+        let fragment = ErrorFragment {
+            title: title.to_string(),
+            login: status == StatusCode::UNAUTHORIZED,
+            message,
+        };
+
+        let mut response = (status, fragment.to_string()).into_response();
+        let headers = response.headers_mut();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        headers.insert("HX-Retarget", HeaderValue::from_static("#content"));
+        headers.insert("HX-Reswap", HeaderValue::from_static("innerHTML"));
+        response.extensions_mut().insert(fragment);
+        response
+    }
+}
+
+// errors are rendered as fragments for htmx, requests made without htmx
+// get the same error wrapped in a full page
+pub async fn error_pages(req: Request<Body>, next: Next<Body>) -> Response {
+    let htmx = req.headers().contains_key("HX-Request");
+    let mut response = next.run(req).await;
+    if htmx {
+        return response;
+    }
+    let Some(ErrorFragment {
+        title,
+        message,
+        login,
+    }) = response.extensions_mut().remove::<ErrorFragment>()
+    else {
+        return response;
+    };
+    (
+        response.status(),
+        ErrorPage {
+            title,
+            message,
+            login,
+        },
+    )
+        .into_response()
+}
@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use surrealdb::sql::Thing;
+
+use crate::{db::Timed, error::AppError, purchase::check_seats, DB};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// holds every seat for the session or none of them, and lets go of any other
+// seats the session was holding for the same showtime
+pub async fn hold_seats(
+    session: &str,
+    showtime: &str,
+    seats: &[i32],
+    minutes: u32,
+) -> Result<(), AppError> {
+    let response = DB
+        .query(
+            r#"
+            BEGIN TRANSACTION;
+
+            UPDATE (
+                SELECT VALUE ->showtime_seat->(seats WHERE held_by = $holder AND seat NOTINSIDE $seat_nums)
+                FROM ONLY type::thing("showtime", $showtime)
+            ) SET held_by = NONE, held_until = NONE;
+
+            LET $seats = SELECT VALUE ->showtime_seat->(seats WHERE seat INSIDE $seat_nums AND available = true
+                AND (held_until = NONE OR held_until <= time::now() OR held_by = $holder))
+            FROM ONLY type::thing("showtime", $showtime);
+            IF array::len($seats) != array::len($seat_nums) {
+                THROW "seat unavailable";
+            };
+            UPDATE $seats SET held_by = $holder, held_until = time::now() + <duration> $hold;
+
+            COMMIT TRANSACTION;
+            "#,
+        )
+        .bind(("holder", Thing::from(("sessions", session))))
+        .bind(("showtime", showtime))
+        .bind(("seat_nums", seats))
+        .bind(("hold", format!("{minutes}m")))
+        .timed()
+        .await?;
+    check_seats(response)?;
+    Ok(())
+}
+
+pub async fn release_seats(session: &str, showtime: &str) -> Result<(), AppError> {
+    DB.query(
+        r#"
+        UPDATE (
+            SELECT VALUE ->showtime_seat->(seats WHERE held_by = $holder)
+            FROM ONLY type::thing("showtime", $showtime)
+        ) SET held_by = NONE, held_until = NONE;
+        "#,
+    )
+    .bind(("holder", Thing::from(("sessions", session))))
+    .bind(("showtime", showtime))
+    .timed()
+    .await?
+    .check()?;
+    Ok(())
+}
+
+pub async fn sweep_expired_holds() {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let query = DB
+            .query(
+                r#"
+                UPDATE seats SET held_by = NONE, held_until = NONE
+                WHERE held_until != NONE AND held_until <= time::now()
+                "#,
+            )
+            .timed()
+            .await;
+        if let Err(err) = query.and_then(|response| response.check()) {
+            tracing::error!("failed to release expired seat holds: {err}");
+        }
+    }
+}
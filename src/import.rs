@@ -0,0 +1,369 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
+
+use crate::DB;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct MovieFields {
+    name: String,
+    genres: Vec<String>,
+    runtime: i32,
+    tagline: String,
+    stars: f32,
+    description: String,
+    image: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MovieFile {
+    id: String,
+    #[serde(flatten)]
+    fields: MovieFields,
+}
+
+#[derive(Debug, Deserialize)]
+struct PeopleFile {
+    director: String,
+    writers: Vec<String>,
+    stars: Vec<Role>,
+    cast: Vec<Role>,
+    producers: Vec<Producer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Role {
+    actor: String,
+    role: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Producer {
+    name: String,
+    position: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Edge {
+    id: Thing,
+    out: Thing,
+    detail: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Credit {
+    Star,
+    Actor,
+    Writer,
+    Director,
+    Producer,
+}
+
+impl Credit {
+    fn table(self) -> &'static str {
+        match self {
+            Credit::Star => "star",
+            Credit::Actor => "actor",
+            Credit::Writer => "writer",
+            Credit::Director => "director",
+            Credit::Producer => "producer",
+        }
+    }
+
+    fn detail_field(self) -> Option<&'static str> {
+        match self {
+            Credit::Star | Credit::Actor => Some("role"),
+            Credit::Producer => Some("position"),
+            Credit::Writer | Credit::Director => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    changes: Vec<String>,
+    conflicts: Vec<String>,
+    unchanged: usize,
+}
+
+pub async fn import(data: &Path) -> anyhow::Result<()> {
+    let mut report = Report::default();
+    let mut people = HashMap::new();
+
+    for path in json_files(&data.join("movies"), "")? {
+        if let Err(err) = import_movie(&path, &mut report).await {
+            report
+                .conflicts
+                .push(format!("{}: {err:#}", path.display()));
+        }
+    }
+
+    for path in json_files(&data.join("people"), "people_")? {
+        if let Err(err) = import_credits(&path, &mut people, &mut report).await {
+            report
+                .conflicts
+                .push(format!("{}: {err:#}", path.display()));
+        }
+    }
+
+    for change in &report.changes {
+        println!("{change}");
+    }
+    println!(
+        "{} changes, {} unchanged, {} conflicts",
+        report.changes.len(),
+        report.unchanged,
+        report.conflicts.len()
+    );
+    for conflict in &report.conflicts {
+        eprintln!("conflict: {conflict}");
+    }
+    Ok(())
+}
+
+fn json_files(dir: &Path, prefix: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        let is_match = path.is_file()
+            && path.extension().is_some_and(|ext| ext == "json")
+            && path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(prefix));
+        if is_match {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> anyhow::Result<T> {
+    let contents = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+async fn import_movie(path: &Path, report: &mut Report) -> anyhow::Result<()> {
+    let MovieFile { id, fields } = read_json(path)?;
+
+    let existing: Option<MovieFields> = DB.select(("movies", id.as_str())).await?;
+    match existing {
+        Some(existing) if existing == fields => {
+            report.unchanged += 1;
+            return Ok(());
+        }
+        Some(existing) => {
+            for field in changed_fields(&existing, &fields) {
+                report.conflicts.push(format!(
+                    "movies:{id} {field} differs, replaced with file value"
+                ));
+            }
+            report.changes.push(format!("updated movies:{id}"));
+        }
+        None => report.changes.push(format!("created movies:{id}")),
+    }
+
+    DB.query("UPDATE type::thing('movies', $id) CONTENT $movie")
+        .bind(("id", &id))
+        .bind(("movie", &fields))
+        .await?
+        .check()?;
+    Ok(())
+}
+
+fn changed_fields(old: &MovieFields, new: &MovieFields) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    if old.name != new.name {
+        fields.push("name");
+    }
+    if old.genres != new.genres {
+        fields.push("genres");
+    }
+    if old.runtime != new.runtime {
+        fields.push("runtime");
+    }
+    if old.tagline != new.tagline {
+        fields.push("tagline");
+    }
+    if old.stars != new.stars {
+        fields.push("stars");
+    }
+    if old.description != new.description {
+        fields.push("description");
+    }
+    if old.image != new.image {
+        fields.push("image");
+    }
+    fields
+}
+
+async fn import_credits(
+    path: &Path,
+    people: &mut HashMap<String, Thing>,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    let Some(movie_id) = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.strip_prefix("people_"))
+    else {
+        return Ok(());
+    };
+    let movie = Thing::from(("movies", movie_id));
+
+    let found: Option<MovieFields> = DB.select(("movies", movie_id)).await?;
+    if found.is_none() {
+        report
+            .conflicts
+            .push(format!("{movie} has credits but no movie, skipped"));
+        return Ok(());
+    }
+
+    let file: PeopleFile = read_json(path)?;
+    let credits = [
+        (Credit::Director, vec![(file.director, None)]),
+        (
+            Credit::Writer,
+            file.writers.into_iter().map(|name| (name, None)).collect(),
+        ),
+        (
+            Credit::Star,
+            file.stars
+                .into_iter()
+                .map(|Role { actor, role }| (actor, Some(role)))
+                .collect(),
+        ),
+        (
+            Credit::Actor,
+            file.cast
+                .into_iter()
+                .map(|Role { actor, role }| (actor, Some(role)))
+                .collect(),
+        ),
+        (
+            Credit::Producer,
+            file.producers
+                .into_iter()
+                .map(|Producer { name, position }| (name, Some(position)))
+                .collect(),
+        ),
+    ];
+
+    for (credit, entries) in credits {
+        let mut wanted = Vec::new();
+        for (name, detail) in entries {
+            let id = person(&name, people, report).await?;
+            wanted.push((id, name, detail));
+        }
+        sync_credits(&movie, credit, wanted, report).await?;
+    }
+    Ok(())
+}
+
+async fn person(
+    name: &str,
+    people: &mut HashMap<String, Thing>,
+    report: &mut Report,
+) -> anyhow::Result<Thing> {
+    if let Some(id) = people.get(name) {
+        return Ok(id.clone());
+    }
+
+    let mut ids: Vec<Thing> = DB
+        .query("SELECT VALUE id FROM people WHERE name = $name")
+        .bind(("name", name))
+        .await?
+        .take(0)?;
+    if ids.len() > 1 {
+        report.conflicts.push(format!(
+            "{} people are named {name}, using {}",
+            ids.len(),
+            ids[0]
+        ));
+    }
+    let id = if ids.is_empty() {
+        let id: Option<Thing> = DB
+            .query("CREATE ONLY people SET name = $name RETURN VALUE id")
+            .bind(("name", name))
+            .await?
+            .take(0)?;
+        let id = id.context("failed to create person")?;
+        report.changes.push(format!("created {id} {name}"));
+        id
+    } else {
+        ids.swap_remove(0)
+    };
+    people.insert(name.to_string(), id.clone());
+    Ok(id)
+}
+
+async fn sync_credits(
+    movie: &Thing,
+    credit: Credit,
+    wanted: Vec<(Thing, String, Option<String>)>,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    let table = credit.table();
+    let detail = credit.detail_field().unwrap_or("NONE");
+
+    let mut existing: Vec<Edge> = DB
+        .query(format!(
+            "SELECT id, out, {detail} AS detail FROM {table} WHERE in = $movie"
+        ))
+        .bind(("movie", movie))
+        .await?
+        .take(0)?;
+
+    for (person, name, value) in wanted {
+        if let Some(index) = existing
+            .iter()
+            .position(|edge| edge.out == person && edge.detail == value)
+        {
+            existing.swap_remove(index);
+            report.unchanged += 1;
+            continue;
+        }
+
+        let set = match credit.detail_field() {
+            Some(field) => format!("SET {field} = $detail"),
+            None => String::new(),
+        };
+        DB.query(format!("RELATE $movie->{table}->$person {set}"))
+            .bind(("movie", movie))
+            .bind(("person", &person))
+            .bind(("detail", &value))
+            .await?
+            .check()?;
+        report.changes.push(format!(
+            "added {table} {name} to {movie}{}",
+            describe(&value)
+        ));
+    }
+
+    for edge in existing {
+        DB.query("DELETE $edge")
+            .bind(("edge", &edge.id))
+            .await?
+            .check()?;
+        report.changes.push(format!(
+            "removed {table} {} from {movie}{}",
+            edge.out,
+            describe(&edge.detail)
+        ));
+    }
+    Ok(())
+}
+
+fn describe(detail: &Option<String>) -> String {
+    match detail {
+        Some(detail) => format!(" ({detail})"),
+        None => String::new(),
+    }
+}
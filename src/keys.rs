@@ -0,0 +1,138 @@
+use std::fs;
+
+use anyhow::{anyhow, Context};
+use axum::{
+    body::Body,
+    extract::State,
+    http::{
+        header::{COOKIE, SET_COOKIE},
+        HeaderValue, Request,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, Key, PrivateCookieJar, SameSite};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{config::CookieConfig, AppState};
+
+pub struct CookieKeys {
+    pub current: Key,
+    pub previous: Vec<Key>,
+}
+
+pub fn load_keys(config: &CookieConfig) -> anyhow::Result<CookieKeys> {
+    let current = match (&config.key, &config.key_file) {
+        (Some(key), _) => decode_key(key)?,
+        (None, Some(path)) => decode_key(&fs::read_to_string(path).with_context(|| {
+            format!("failed to read cookie key file {}", path.display())
+        })?)?,
+        (None, None) => {
+            tracing::warn!("no cookie key configured, sessions will not survive a restart");
+            Key::generate()
+        }
+    };
+
+    let previous = config
+        .previous_keys
+        .iter()
+        .map(|key| decode_key(key))
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(CookieKeys { current, previous })
+}
+
+fn decode_key(encoded: &str) -> anyhow::Result<Key> {
+    let bytes = STANDARD
+        .decode(encoded.trim())
+        .context("cookie key is not valid base64")?;
+    Key::try_from(bytes.as_slice()).map_err(|_| anyhow!("cookie key must be at least 64 bytes"))
+}
+
+// cookies encrypted with a previous key are re-encrypted with the current key,
+// both for the handlers of this request and for the browser
+pub async fn rotate_cookie_keys(
+    State(state): State<AppState>,
+    mut req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if state.previous_keys.is_empty() {
+        return next.run(req).await;
+    }
+
+    let current = PrivateCookieJar::new(state.key.clone());
+    let mut reissued = PrivateCookieJar::new(state.key.clone());
+    let mut rotated = false;
+    for cookie in CookieJar::from_headers(req.headers()).iter() {
+        if current.decrypt(cookie.clone()).is_some() {
+            continue;
+        }
+        let Some(plain) = state
+            .previous_keys
+            .iter()
+            .find_map(|key| PrivateCookieJar::new(key.clone()).decrypt(cookie.clone()))
+        else {
+            continue;
+        };
+        let cookie = Cookie::build(plain.name().to_string(), plain.value().to_string())
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .finish();
+        reissued = reissued.add(cookie);
+        rotated = true;
+    }
+    if !rotated {
+        return next.run(req).await;
+    }
+
+    let set_cookies: Vec<HeaderValue> = reissued
+        .into_response()
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .cloned()
+        .collect();
+
+    let mut pairs: Vec<String> = req
+        .headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .map(|pair| pair.trim().to_string())
+        .filter(|pair| !pair.is_empty())
+        .collect();
+    for value in &set_cookies {
+        let (Some(pair), Some(name)) = (cookie_pair(value), cookie_name(value)) else {
+            continue;
+        };
+        pairs.retain(|old| old.split_once('=').map(|(old, _)| old) != Some(name));
+        pairs.push(pair.to_string());
+    }
+    if let Ok(header) = HeaderValue::from_str(&pairs.join("; ")) {
+        req.headers_mut().insert(COOKIE, header);
+    }
+
+    let mut response = next.run(req).await;
+    for value in set_cookies {
+        let name = cookie_name(&value);
+        let overwritten = response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .any(|set| cookie_name(set) == name);
+        if !overwritten {
+            response.headers_mut().append(SET_COOKIE, value);
+        }
+    }
+    response
+}
+
+fn cookie_pair(value: &HeaderValue) -> Option<&str> {
+    value.to_str().ok()?.split(';').next()
+}
+
+fn cookie_name(value: &HeaderValue) -> Option<&str> {
+    cookie_pair(value)?.split_once('=').map(|(name, _)| name)
+}
@@ -1,14 +1,14 @@
-use crate::DB;
+use crate::{auth::OptionalUser, csrf::CsrfToken, db::Timed, error::AppError, DB};
 use askama::Template;
-use axum::{http::StatusCode, response::Result};
-use axum_extra::extract::PrivateCookieJar;
 use serde::{Deserialize, Serialize};
 use surrealdb::sql::Thing;
 
 #[derive(Template)]
 #[template(path = "index.html")]
 pub struct Index {
-    logged_in: bool,
+    pub logged_in: bool,
+    pub content: String,
+    pub csrf: String,
 }
 
 #[derive(Template)]
@@ -58,33 +58,21 @@ pub struct Time {
     pub time: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Record {
-    id: Thing,
-}
-
-pub async fn index(jar: PrivateCookieJar) -> Index {
-    let Some(session) = jar.get("session") else {
-        return Index { logged_in: false };
-    };
-    let Ok(Some(_)) = DB
-        .select::<Option<Record>>(("sessions", session.value()))
-        .await
-    else {
-        return Index { logged_in: false };
-    };
-    Index { logged_in: true }
+pub async fn index(OptionalUser(user): OptionalUser, CsrfToken(csrf): CsrfToken) -> Index {
+    Index {
+        logged_in: user.is_some(),
+        content: "/home".to_string(),
+        csrf,
+    }
 }
 
-pub async fn home() -> Result<HomePage> {
-    let Ok(movies) = DB.select("movies").await else {
-        return Err(StatusCode::NOT_FOUND.into());
-    };
+pub async fn home() -> Result<HomePage, AppError> {
+    let movies = DB.select("movies").timed().await?;
     Ok(HomePage { movies })
 }
 
-pub async fn showtimes() -> Result<ShowtimePage> {
-    let query = DB
+pub async fn showtimes() -> Result<ShowtimePage, AppError> {
+    let mut query = DB
         .query(
             r#"
             SELECT *, (
@@ -96,13 +84,9 @@ pub async fn showtimes() -> Result<ShowtimePage> {
             FROM movies
             "#,
         )
-        .await;
-    let Ok(mut qeury) = query else {
-        return Err(StatusCode::NOT_FOUND.into());
-    };
-    let Ok(movies) = qeury.take(0) else {
-        return Err(StatusCode::NOT_FOUND.into());
-    };
+        .timed()
+        .await?;
+    let movies = query.take(0)?;
     Ok(ShowtimePage { movies })
 }
 
@@ -0,0 +1,304 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{ConnectInfo, FromRef, FromRequestParts, State},
+    http::{request::Parts, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    config::{Config, LimitsConfig},
+    error::AppError,
+};
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+// the address of the client, taken from X-Forwarded-For only when the server
+// is configured to sit behind a proxy that sets it
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+        if config.server.trust_forwarded_for {
+            let forwarded = parts
+                .headers
+                .get("X-Forwarded-For")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(',').next())
+                .and_then(|ip| ip.trim().parse().ok());
+            if let Some(ip) = forwarded {
+                return Ok(ClientIp(ip));
+            }
+        }
+        match parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(addr)) => Ok(ClientIp(addr.ip())),
+            None => Err(AppError::Internal(anyhow::anyhow!(
+                "no client address on request"
+            ))),
+        }
+    }
+}
+
+struct Failures {
+    count: u32,
+    locked_until: Option<Instant>,
+    last: Instant,
+}
+
+// failed logins per account and per client address. after a few free tries
+// each failure locks the key for twice as long as the last, and enough of them
+// lock it for the full lockout
+pub struct LoginGuard {
+    config: LimitsConfig,
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+impl LoginGuard {
+    pub fn new(config: &LimitsConfig) -> LoginGuard {
+        LoginGuard {
+            config: config.clone(),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn keys(email: &str, ip: IpAddr) -> [String; 2] {
+        [
+            format!("account:{}", email.trim().to_lowercase()),
+            format!("ip:{ip}"),
+        ]
+    }
+
+    // counts the attempt against every key before the password is checked, so
+    // parallel guesses can't all slip in before the first failure is recorded.
+    // a locked key turns the attempt away with how much longer it stays locked
+    pub fn attempt(&self, keys: &[String]) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        let locked = keys
+            .iter()
+            .filter_map(|key| failures.get(key)?.locked_until)
+            .filter(|until| *until > now)
+            .max();
+        if let Some(until) = locked {
+            return Err(until - now);
+        }
+        for key in keys {
+            let entry = failures.entry(key.clone()).or_insert(Failures {
+                count: 0,
+                locked_until: None,
+                last: now,
+            });
+            entry.count += 1;
+            entry.last = now;
+            if let Some(lock) = self.lock_for(entry.count) {
+                entry.locked_until = Some(now + lock);
+            }
+        }
+        Ok(())
+    }
+
+    // the account starts over, the address only takes back this attempt. it
+    // wasn't locked when the attempt was let in
+    pub fn succeed(&self, keys: &[String]) {
+        let mut failures = self.failures.lock().unwrap();
+        failures.remove(&keys[0]);
+        for key in &keys[1..] {
+            if let Some(entry) = failures.get_mut(key) {
+                entry.count = entry.count.saturating_sub(1);
+                entry.locked_until = None;
+            }
+        }
+    }
+
+    fn lock_for(&self, count: u32) -> Option<Duration> {
+        let config = &self.config;
+        if count >= config.login_lockout_after {
+            return Some(Duration::from_secs(config.login_lockout_minutes * 60));
+        }
+        let over = count.checked_sub(config.login_free_attempts + 1)?;
+        let backoff = config
+            .login_backoff_seconds
+            .saturating_mul(2u64.saturating_pow(over))
+            .min(config.login_max_backoff_seconds);
+        Some(Duration::from_secs(backoff))
+    }
+
+    // failures are forgotten once a key has been quiet for a full lockout
+    fn prune(&self) {
+        let forget = Duration::from_secs(self.config.login_lockout_minutes * 60);
+        let now = Instant::now();
+        self.failures.lock().unwrap().retain(|_, entry| {
+            now - entry.last < forget || entry.locked_until.is_some_and(|until| until > now)
+        });
+    }
+}
+
+// a fixed window of requests per client address
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    hits: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &LimitsConfig) -> RateLimiter {
+        RateLimiter {
+            limit: config.requests_per_minute,
+            window: Duration::from_secs(60),
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn hit(&self, ip: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut hits = self.hits.lock().unwrap();
+        let (start, count) = hits.entry(ip).or_insert((now, 0));
+        if now - *start >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= self.limit {
+            return Err(self.window - (now - *start));
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    fn prune(&self) {
+        let now = Instant::now();
+        self.hits
+            .lock()
+            .unwrap()
+            .retain(|_, (start, _)| now - *start < self.window);
+    }
+}
+
+pub async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    ClientIp(ip): ClientIp,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    match limiter.hit(ip) {
+        Ok(()) => next.run(req).await,
+        Err(wait) => AppError::TooManyRequests(format!(
+            "Too many requests. Please try again in {}.",
+            wait_message(wait)
+        ))
+        .into_response(),
+    }
+}
+
+pub fn wait_message(wait: Duration) -> String {
+    let seconds = wait.as_secs() + (wait.subsec_nanos() > 0) as u64;
+    match seconds {
+        0 | 1 => "a second".to_string(),
+        2..=59 => format!("{seconds} seconds"),
+        60..=119 => "a minute".to_string(),
+        _ => format!("{} minutes", seconds.div_ceil(60)),
+    }
+}
+
+pub async fn prune_limits(guard: Arc<LoginGuard>, limiter: Arc<RateLimiter>) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        guard.prune();
+        limiter.prune();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(seconds: u64) -> Option<Duration> {
+        Some(Duration::from_secs(seconds))
+    }
+
+    #[test]
+    fn backs_off_after_the_free_attempts() {
+        let guard = LoginGuard::new(&LimitsConfig::default());
+        assert_eq!(guard.lock_for(1), None);
+        assert_eq!(guard.lock_for(3), None);
+        assert_eq!(guard.lock_for(4), secs(2));
+        assert_eq!(guard.lock_for(5), secs(4));
+        assert_eq!(guard.lock_for(6), secs(8));
+        assert_eq!(guard.lock_for(9), secs(64));
+    }
+
+    #[test]
+    fn locks_out_fully_after_enough_failures() {
+        let guard = LoginGuard::new(&LimitsConfig::default());
+        assert_eq!(guard.lock_for(10), secs(15 * 60));
+        assert_eq!(guard.lock_for(11), secs(15 * 60));
+    }
+
+    #[test]
+    fn back_off_is_capped() {
+        let guard = LoginGuard::new(&LimitsConfig {
+            login_free_attempts: 0,
+            login_backoff_seconds: 100,
+            login_max_backoff_seconds: 300,
+            login_lockout_after: 100,
+            ..LimitsConfig::default()
+        });
+        assert_eq!(guard.lock_for(1), secs(100));
+        assert_eq!(guard.lock_for(2), secs(200));
+        assert_eq!(guard.lock_for(3), secs(300));
+        assert_eq!(guard.lock_for(80), secs(300));
+    }
+
+    #[test]
+    fn attempts_count_before_the_password_is_checked() {
+        let guard = LoginGuard::new(&LimitsConfig {
+            login_free_attempts: 0,
+            ..LimitsConfig::default()
+        });
+        let keys = LoginGuard::keys("someone@example.com", "127.0.0.1".parse().unwrap());
+        assert!(guard.attempt(&keys).is_ok());
+        assert!(guard.attempt(&keys).is_err());
+    }
+
+    #[test]
+    fn success_clears_the_account_and_takes_back_the_attempt() {
+        let guard = LoginGuard::new(&LimitsConfig {
+            login_free_attempts: 1,
+            ..LimitsConfig::default()
+        });
+        let keys = LoginGuard::keys(" Someone@Example.com", "127.0.0.1".parse().unwrap());
+        assert_eq!(keys[0], "account:someone@example.com");
+        assert!(guard.attempt(&keys).is_ok());
+        assert!(guard.attempt(&keys).is_ok());
+        guard.succeed(&keys);
+        let failures = guard.failures.lock().unwrap();
+        assert!(!failures.contains_key(&keys[0]));
+        assert_eq!(failures[&keys[1]].count, 1);
+        assert!(failures[&keys[1]].locked_until.is_none());
+    }
+
+    #[test]
+    fn wait_messages_round_up() {
+        assert_eq!(wait_message(Duration::from_millis(500)), "a second");
+        assert_eq!(wait_message(Duration::from_secs(30)), "30 seconds");
+        assert_eq!(wait_message(Duration::from_secs(60)), "a minute");
+        assert_eq!(wait_message(Duration::from_secs(15 * 60 - 1)), "15 minutes");
+    }
+}
@@ -0,0 +1,149 @@
+use std::{collections::HashSet, time::Duration};
+
+use askama::Template;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, State,
+    },
+    response::Response,
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use surrealdb::{
+    sql::{Datetime, Thing},
+    Action,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    auth::OptionalUser,
+    db::Timed,
+    error::AppError,
+    seating::Seat,
+    seatmap::{showtime_layout, Layout, SeatType},
+    DB,
+};
+
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Template)]
+#[template(path = "seat.html")]
+pub struct SeatTile {
+    seat: Seat,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SeatUpdate {
+    id: Thing,
+    seat: i32,
+    available: bool,
+    held_by: Option<Thing>,
+    held_until: Option<Datetime>,
+}
+
+impl SeatUpdate {
+    // the session holding a seat still sees it as available
+    fn to_seat(&self, layout: &Layout, session: Option<&Thing>) -> Seat {
+        let held = self
+            .held_until
+            .as_ref()
+            .is_some_and(|until| **until > chrono::Utc::now())
+            && self.held_by.as_ref() != session;
+        Seat {
+            available: self.available,
+            held,
+            seat: self.seat,
+            id: self.id.clone(),
+            position: layout.position(self.seat),
+        }
+    }
+}
+
+pub fn seat_updates() -> broadcast::Sender<SeatUpdate> {
+    broadcast::channel(CHANNEL_CAPACITY).0
+}
+
+// one live query on seats feeds every connected seating page
+pub async fn watch_seats(updates: broadcast::Sender<SeatUpdate>) {
+    loop {
+        match DB.select::<Vec<SeatUpdate>>("seats").live().await {
+            Ok(mut stream) => {
+                while let Some(notification) = stream.next().await {
+                    match notification {
+                        Ok(notification) if notification.action != Action::Delete => {
+                            let _ = updates.send(notification.data);
+                        }
+                        Ok(_) => {}
+                        Err(err) => tracing::warn!("bad seat notification: {err}"),
+                    }
+                }
+                tracing::warn!("seat live query ended, restarting");
+            }
+            Err(err) => tracing::error!("failed to start seat live query: {err}"),
+        }
+        tokio::time::sleep(RETRY_DELAY).await;
+    }
+}
+
+pub async fn live_seats(
+    OptionalUser(user): OptionalUser,
+    State(updates): State<broadcast::Sender<SeatUpdate>>,
+    Path(id): Path<String>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    let mut query = DB
+        .query(
+            r#"
+            SELECT VALUE ->showtime_seat->seats
+            FROM ONLY type::thing("showtime", $id)
+            "#,
+        )
+        .bind(("id", &id))
+        .timed()
+        .await?;
+    let seats: Vec<Thing> = query.take(0)?;
+    // keyed by the raw id, Thing can hold values with interior mutability
+    let seats: HashSet<String> = seats.into_iter().map(|seat| seat.id.to_raw()).collect();
+    if seats.is_empty() {
+        return Err(AppError::NotFound);
+    }
+
+    let layout = showtime_layout(&id).await?;
+    let session = user.map(|user| Thing::from(("sessions", user.session.as_str())));
+    let updates = updates.subscribe();
+    Ok(ws.on_upgrade(move |socket| send_seats(socket, seats, layout, session, updates)))
+}
+
+async fn send_seats(
+    mut socket: WebSocket,
+    seats: HashSet<String>,
+    layout: Layout,
+    session: Option<Thing>,
+    mut updates: broadcast::Receiver<SeatUpdate>,
+) {
+    loop {
+        tokio::select! {
+            update = updates.recv() => match update {
+                Ok(update) if seats.contains(&update.id.id.to_raw()) => {
+                    let tile = SeatTile {
+                        seat: update.to_seat(&layout, session.as_ref()),
+                    };
+                    if socket.send(Message::Text(tile.to_string())).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("seating page missed {skipped} seat updates");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::Instant;
+
+use anyhow::{anyhow, Context};
+use axum::{body::Body, extract::MatchedPath, http::Request, middleware::Next, response::Response};
+use tracing::{field, Instrument, Span};
+use tracing_subscriber::EnvFilter;
+
+use crate::config::{LogFormat, LoggingConfig};
+
+pub fn init(config: &LoggingConfig) -> anyhow::Result<()> {
+    let filter = EnvFilter::try_new(&config.level)
+        .with_context(|| format!("invalid logging.level {}", config.level))?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match config.format {
+        LogFormat::Pretty => builder.try_init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .try_init(),
+    }
+    .map_err(|err| anyhow!("failed to start logging: {err}"))
+}
+
+// account is filled in by the CurrentUser extractor once the session is resolved
+pub async fn trace_requests(req: Request<Body>, next: Next<Body>) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| req.uri().path().to_string());
+    let span = tracing::info_span!(
+        "request",
+        method = %req.method(),
+        route,
+        status = field::Empty,
+        latency_ms = field::Empty,
+        account = field::Empty,
+    );
+
+    let start = Instant::now();
+    async move {
+        let response = next.run(req).await;
+        let span = Span::current();
+        span.record("status", response.status().as_u16());
+        span.record("latency_ms", start.elapsed().as_millis() as u64);
+        if response.status().is_server_error() {
+            tracing::error!("request failed");
+        } else {
+            tracing::info!("request finished");
+        }
+        response
+    }
+    .instrument(span)
+    .await
+}
@@ -1,24 +1,51 @@
+use std::sync::Arc;
+
 use askama::Template;
 use askama_axum::IntoResponse;
 use axum::{
-    extract::Form,
-    http::StatusCode,
+    extract::{Form, State},
     response::{Redirect, Response},
 };
-use axum_extra::extract::cookie::{Cookie, PrivateCookieJar};
+use axum_extra::extract::cookie::{Cookie, Key, PrivateCookieJar};
 use regex::Regex;
 use serde::Deserialize;
 use surrealdb::sql::Thing;
 
-use crate::DB;
+use crate::{
+    auth::OptionalUser,
+    config::Config,
+    db::Timed,
+    error::AppError,
+    limits::{wait_message, ClientIp, LoginGuard},
+    mail::Mailer,
+    password::{hash_password, verify_password, Verified},
+    session::{end_session, start_session},
+    verification::send_verification,
+    DB,
+};
 
 #[derive(Template)]
 #[template(path = "login.html")]
 pub struct Login {
-    email: String,
-    password: String,
-    valid_email: bool,
-    account_found: bool,
+    pub email: String,
+    pub password: String,
+    pub valid_email: bool,
+    pub account_found: bool,
+    pub notice: Option<&'static str>,
+    pub lockout: Option<String>,
+}
+
+impl Default for Login {
+    fn default() -> Self {
+        Login {
+            email: String::new(),
+            password: String::new(),
+            valid_email: true,
+            account_found: true,
+            notice: None,
+            lockout: None,
+        }
+    }
 }
 
 #[derive(Template)]
@@ -41,107 +68,99 @@ pub struct Account {
 }
 
 #[derive(Deserialize)]
-struct Record {
-    #[allow(dead_code)]
+struct Credentials {
     id: Thing,
+    password: String,
 }
 
-pub async fn get_login(jar: PrivateCookieJar) -> Response {
-    let Some(session) = jar.get("session") else {
-        return Login {
-            email: String::new(),
-            password: String::new(),
-            valid_email: true,
-            account_found: true,
-        }
-        .into_response();
-    };
-
-    let Ok(Some(_)) = DB
-        .select::<Option<Record>>(("sessions", session.value()))
-        .await
-    else {
-        return Login {
-            email: String::new(),
-            password: String::new(),
-            valid_email: true,
-            account_found: true,
-        }
-        .into_response();
-    };
-    Redirect::to("/account").into_response()
+pub async fn get_login(OptionalUser(user): OptionalUser) -> Response {
+    if user.is_some() {
+        return Redirect::to("/account").into_response();
+    }
+    Login::default().into_response()
 }
 
+// wrong passwords count against both the account and the client address,
+// while either is locked no password is checked at all
 pub async fn post_login(
     jar: PrivateCookieJar,
+    State(guard): State<Arc<LoginGuard>>,
+    ClientIp(ip): ClientIp,
     Form(Account { email, password }): Form<Account>,
-) -> Response {
+) -> Result<Response, AppError> {
     let valid_email = is_valid_email(&email);
     if !valid_email {
-        return Login {
+        return Ok(Login {
             email,
             password,
             valid_email,
-            account_found: true,
+            ..Login::default()
+        }
+        .into_response());
+    }
+    let keys = LoginGuard::keys(&email, ip);
+    if let Err(wait) = guard.attempt(&keys) {
+        return Ok(Login {
+            email,
+            lockout: Some(format!(
+                "Too many failed log in attempts. Please try again in {}.",
+                wait_message(wait)
+            )),
+            ..Login::default()
         }
-        .into_response();
+        .into_response());
     }
-    let query = DB
+    let mut query = DB
         .query(
             r#"
-            SELECT VALUE id
+            SELECT id, password
             FROM ONLY accounts
-            WHERE email = $email AND password = $password
+            WHERE email = $email
+            LIMIT 1
             "#,
         )
         .bind(("email", &email))
-        .bind(("password", &password))
-        .await;
-    let Ok(mut query) = query else {
-        return StatusCode::NOT_ACCEPTABLE.into_response();
-    };
+        .timed()
+        .await?;
 
-    let Ok(Some(id)) = query.take::<Option<Thing>>(0) else {
-        return Login {
+    let Ok(Some(Credentials { id, password: hash })) = query.take::<Option<Credentials>>(0) else {
+        return Ok(Login {
             email,
             password,
-            valid_email: true,
             account_found: false,
+            ..Login::default()
         }
-        .into_response();
+        .into_response());
     };
 
-    let query = DB
-        .query(
-            r#"            
-            BEGIN TRANSACTION;
-
-            LET $new_session = CREATE ONLY sessions;
-
-            RELATE $new_session->account_session->$user 
-                SET time = time::now();
-
-            RETURN $new_session.id;
-
-            COMMIT TRANSACTION;
-            "#,
-        )
-        .bind(("user", &id))
-        .await;
-
-    let Ok(mut query) = query else {
-        return StatusCode::NOT_ACCEPTABLE.into_response();
-    };
+    match verify_password(password.clone(), hash).await {
+        Verified::Valid => {}
+        Verified::Legacy => {
+            let hash = hash_password(password).await?;
+            DB.query("UPDATE $user SET password = $password")
+                .bind(("user", &id))
+                .bind(("password", hash))
+                .timed()
+                .await?
+                .check()?;
+        }
+        Verified::Invalid => {
+            return Ok(Login {
+                email,
+                password,
+                account_found: false,
+                ..Login::default()
+            }
+            .into_response());
+        }
+    }
+    guard.succeed(&keys);
 
-    let Ok(Some(session)): Result<Option<Thing>, _> = query.take(0) else {
-        return StatusCode::NOT_ACCEPTABLE.into_response();
-    };
-    let mut jar = jar
-        .add(Cookie::new("session", session.id.to_raw().clone()))
-        .into_response();
+    let jar = start_session(jar, &id).await?;
+    let mut jar = jar.into_response();
     jar.headers_mut()
         .insert("HX-Redirect", "/".parse().unwrap());
-    jar
+    Ok(jar)
 }
 
 pub async fn sign_up() -> SignUp {
@@ -155,73 +174,68 @@ pub async fn sign_up() -> SignUp {
 
 pub async fn create_account(
     jar: PrivateCookieJar,
+    State(config): State<Arc<Config>>,
+    State(key): State<Key>,
+    State(mailer): State<Mailer>,
     Form(Account { email, password }): Form<Account>,
-) -> Response {
+) -> Result<Response, AppError> {
     let valid_email = is_valid_email(&email);
     let valid_password = is_valid_password(&password);
     if !valid_email || !valid_password {
-        return SignUp {
+        return Ok(SignUp {
             email,
             valid_email,
             valid_password,
             account_found: false,
         }
-        .into_response();
+        .into_response());
     }
 
-    let query = DB
+    let hash = hash_password(password).await?;
+
+    let mut query = DB
         .query(
             r#"
-            BEGIN TRANSACTION;
-
-            LET $user = CREATE ONLY accounts SET email = $email, password = $password;
-
-            LET $new_session = CREATE ONLY sessions;
-
-            RELATE $new_session->account_session->$user 
-                SET time = time::now();
-
-            RETURN $new_session.id;
-
-            COMMIT TRANSACTION;
+            CREATE ONLY accounts SET email = $email, password = $password, verified_at = NONE,
+                role = "customer"
+            RETURN VALUE id;
             "#,
         )
         .bind(("email", &email))
-        .bind(("password", &password))
-        .await;
+        .bind(("password", hash))
+        .timed()
+        .await?;
 
-    let Ok(mut query) = query else {
-        return StatusCode::NOT_ACCEPTABLE.into_response();
-    };
-
-    let Ok(Some(session)): Result<Option<Thing>, _> = query.take(0) else {
-        return SignUp {
+    let Ok(Some(user)): Result<Option<Thing>, _> = query.take(0) else {
+        return Ok(SignUp {
             email,
             valid_email,
             valid_password,
             account_found: true,
         }
-        .into_response();
+        .into_response());
     };
-    let mut jar = jar
-        .add(Cookie::new("session", session.id.to_raw().clone()))
-        .into_response();
+    send_verification(&config, &key, &mailer, &user, &email).await?;
+    let jar = start_session(jar, &user).await?;
+    let mut jar = jar.into_response();
     jar.headers_mut()
         .insert("HX-Redirect", "/".parse().unwrap());
-    jar
+    Ok(jar)
 }
 
-fn is_valid_email(email: &String) -> bool {
+pub fn is_valid_email(email: &str) -> bool {
     let email_pattern = Regex::new(r#"^[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}$"#).unwrap();
     email_pattern.is_match(email)
 }
 
-fn is_valid_password(password: &String) -> bool {
+pub fn is_valid_password(password: &str) -> bool {
     !password.is_empty()
 }
 
-pub async fn logout(jar: PrivateCookieJar) -> Response {
-    println!("test");
-    let jar = jar.remove(Cookie::named("session"));
-    (jar, Redirect::to("/")).into_response()
+pub async fn logout(jar: PrivateCookieJar) -> Result<Response, AppError> {
+    if let Some(session) = jar.get("session") {
+        end_session(session.value()).await?;
+    }
+    let jar = jar.remove(Cookie::build("session", "").path("/").finish());
+    Ok((jar, Redirect::to("/")).into_response())
 }
@@ -0,0 +1,109 @@
+use std::{fs, sync::Arc};
+
+use anyhow::Context;
+use lettre::{
+    message::{Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+    AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+
+use crate::config::{MailConfig, MailTransport, SmtpTls};
+
+enum Transport {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    File(AsyncFileTransport<Tokio1Executor>),
+    Stub,
+}
+
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: MultiPart,
+}
+
+#[derive(Clone)]
+pub struct Mailer {
+    transport: Arc<Transport>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    pub fn new(config: &MailConfig) -> anyhow::Result<Mailer> {
+        let transport = match config.transport {
+            MailTransport::Smtp => {
+                let host = config.smtp_host.as_str();
+                let mut builder = match config.smtp_tls {
+                    SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                        .with_context(|| format!("invalid smtp host {host}"))?,
+                    SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+                        .with_context(|| format!("invalid smtp host {host}"))?,
+                    SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+                };
+                if let Some(port) = config.smtp_port {
+                    builder = builder.port(port);
+                }
+                if let (Some(username), Some(password)) =
+                    (&config.smtp_username, &config.smtp_password)
+                {
+                    builder = builder
+                        .credentials(Credentials::new(username.to_string(), password.to_string()));
+                }
+                Transport::Smtp(builder.build())
+            }
+            MailTransport::File => {
+                fs::create_dir_all(&config.dir).with_context(|| {
+                    format!("failed to create mail dir {}", config.dir.display())
+                })?;
+                Transport::File(AsyncFileTransport::new(&config.dir))
+            }
+            MailTransport::Stub => Transport::Stub,
+        };
+        let from = config
+            .from
+            .parse()
+            .with_context(|| format!("invalid mail.from {}", config.from))?;
+        Ok(Mailer {
+            transport: Arc::new(transport),
+            from,
+        })
+    }
+
+    pub async fn send(&self, email: Email) -> anyhow::Result<()> {
+        let to: Mailbox = email
+            .to
+            .parse()
+            .with_context(|| format!("invalid recipient {}", email.to))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&email.subject)
+            .multipart(email.body)?;
+        match &*self.transport {
+            Transport::Smtp(transport) => {
+                transport.send(message).await?;
+            }
+            Transport::File(transport) => {
+                transport.send(message).await?;
+            }
+            Transport::Stub => {
+                tracing::info!(
+                    to = %email.to,
+                    subject = %email.subject,
+                    "email not sent, mail.transport is stub"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // the request doesn't wait on the mail server, failures are only logged
+    pub fn send_later(&self, email: Email) {
+        let mailer = self.clone();
+        tokio::spawn(async move {
+            let subject = email.subject.clone();
+            if let Err(err) = mailer.send(email).await {
+                tracing::error!("failed to send \"{subject}\" email: {err:#}");
+            }
+        });
+    }
+}
@@ -1,47 +1,87 @@
+use std::{net::SocketAddr, sync::Arc};
+
 use account::*;
+use admin::{create_admin, roles_page, set_role};
 use askama::Template;
+use auth::require_user;
 use axum::{
     extract::FromRef,
+    middleware,
     routing::{get, get_service, post},
     Router,
 };
 use axum_extra::extract::cookie::Key;
+use cli::Command;
+use config::Config;
+use csrf::verify_csrf;
+use db::DB;
+use error::error_pages;
+use holds::sweep_expired_holds;
+use import::import;
+use keys::{load_keys, rotate_cookie_keys};
 use landing::*;
+use limits::{prune_limits, rate_limit, LoginGuard, RateLimiter};
+use live::{live_seats, seat_updates, watch_seats, SeatUpdate};
+use logging::trace_requests;
 use login::*;
+use mail::Mailer;
+use migrations::{require_current_schema, run_migrations};
 use movie::*;
-use once_cell::sync::Lazy;
+use payment::Payments;
 use purchase::*;
+use reset::*;
 use seating::*;
-use surrealdb::{
-    engine::remote::ws::{Client, Ws},
-    opt::auth::Root,
-    Surreal,
-};
+use session::purge_expired_sessions;
+use tokio::sync::broadcast;
 use tower_http::services::ServeDir;
+use verification::{resend_verification, verify_email};
 
 mod account;
+mod admin;
+mod auth;
+mod card;
+mod cli;
+mod config;
+mod csrf;
+mod db;
+mod error;
+mod holds;
+mod import;
+mod keys;
 mod landing;
+mod limits;
+mod live;
+mod logging;
 mod login;
+mod mail;
+mod migrations;
 mod movie;
+mod password;
+mod payment;
+mod pricing;
 mod purchase;
+mod reset;
+mod roles;
 mod seating;
+mod seatmap;
+mod session;
+mod tokens;
+mod verification;
 
 #[derive(Template)]
 #[template(path = "temp.html")]
 pub struct Temp {}
 
-const ADDR: &str = "127.0.0.1:8080";
-const DB_ADDR: &str = "127.0.0.1:8000";
-const ROOT: Root = Root {
-    username: "root",
-    password: "root",
-};
-
-static DB: Lazy<Surreal<Client>> = Lazy::new(Surreal::init);
-
 #[derive(Clone)]
 struct AppState {
     key: Key,
+    previous_keys: Vec<Key>,
+    config: Arc<Config>,
+    seat_updates: broadcast::Sender<SeatUpdate>,
+    payments: Payments,
+    mailer: Mailer,
+    login_guard: Arc<LoginGuard>,
+    rate_limiter: Arc<RateLimiter>,
 }
 
 impl FromRef<AppState> for Key {
@@ -50,50 +90,158 @@ impl FromRef<AppState> for Key {
     }
 }
 
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for broadcast::Sender<SeatUpdate> {
+    fn from_ref(state: &AppState) -> Self {
+        state.seat_updates.clone()
+    }
+}
+
+impl FromRef<AppState> for Payments {
+    fn from_ref(state: &AppState) -> Self {
+        state.payments.clone()
+    }
+}
+
+impl FromRef<AppState> for Mailer {
+    fn from_ref(state: &AppState) -> Self {
+        state.mailer.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<LoginGuard> {
+    fn from_ref(state: &AppState) -> Self {
+        state.login_guard.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<RateLimiter> {
+    fn from_ref(state: &AppState) -> Self {
+        state.rate_limiter.clone()
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    DB.connect::<Ws>(DB_ADDR).await?;
-    DB.signin(ROOT).await?;
-    DB.use_ns("theater").use_db("theater").await?;
+    let command = Command::from_args()?;
+    let config = Config::load()?;
+    logging::init(&config.logging)?;
+    db::connect(&config.database).await?;
 
+    match command {
+        Command::Migrate => run_migrations().await,
+        Command::Import(dir) => {
+            require_current_schema().await?;
+            import(&dir).await
+        }
+        Command::CreateAdmin(email) => {
+            require_current_schema().await?;
+            create_admin(&email).await
+        }
+        Command::Serve => serve(config).await,
+    }
+}
+
+async fn serve(config: Config) -> anyhow::Result<()> {
+    if config.database.auto_migrate {
+        run_migrations().await?;
+    } else {
+        require_current_schema().await?;
+    }
+
+    tokio::spawn(purge_expired_sessions());
+    tokio::spawn(sweep_expired_holds());
+
+    let seat_updates = seat_updates();
+    tokio::spawn(watch_seats(seat_updates.clone()));
+
+    let addr = config.server.addr;
+    let keys = load_keys(&config.cookies)?;
+    let payments = Payments::new(&config.payments);
+    let mailer = Mailer::new(&config.mail)?;
+    let login_guard = Arc::new(LoginGuard::new(&config.limits));
+    let rate_limiter = Arc::new(RateLimiter::new(&config.limits));
+    tokio::spawn(prune_limits(login_guard.clone(), rate_limiter.clone()));
     let state = AppState {
-        key: Key::generate(),
+        key: keys.current,
+        previous_keys: keys.previous,
+        config: Arc::new(config),
+        seat_updates,
+        payments,
+        mailer,
+        login_guard,
+        rate_limiter,
     };
 
+    let rate_limited = middleware::from_fn_with_state(state.clone(), rate_limit);
+
     let purchase_routes = Router::new()
-        .route("/:id/:seat", get(purchase))
-        .route("/:id/:seat/:movie/:time", post(complete_purchase));
+        .route("/:id", get(purchase))
+        .route("/:id", post(complete_purchase).layer(rate_limited.clone()))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_user));
 
     let seating_routes = Router::new()
+        .route("/:id/select", get(select_seats))
+        .route("/:id/release", post(release))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_user))
         .route("/:id", get(seating))
-        .route("/:id/:seat", get(select_seat))
+        .route("/:id/live", get(live_seats))
         .route("/times/:id", get(times));
 
     let account_routes = Router::new()
         .route("/", get(tickets))
-        .route("/search", get(search_tickets));
+        .route("/search", get(search_tickets))
+        .route("/tickets/:id/cancel", post(cancel_ticket))
+        .route(
+            "/verify_email",
+            post(resend_verification).layer(rate_limited.clone()),
+        )
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_user));
+
+    let admin_routes = Router::new()
+        .route("/roles", get(roles_page))
+        .route("/roles", post(set_role))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_user));
 
     let app = Router::new()
         .route("/", get(index))
         .route("/login", get(get_login))
-        .route("/login", post(post_login))
+        .route("/login", post(post_login).layer(rate_limited.clone()))
         .route("/logout", post(logout))
         .route("/sign_up", get(sign_up))
-        .route("/sign_up", post(create_account))
+        .route("/sign_up", post(create_account).layer(rate_limited.clone()))
+        .route("/forgot_password", get(forgot_password))
+        .route("/forgot_password", post(send_reset).layer(rate_limited))
+        .route("/reset_password", get(reset_password_page))
+        .route("/reset_password", post(reset_password))
+        .route("/verify_email", get(verify_email))
         .route("/home", get(home))
         .route("/footer", get(footer))
         .route("/showtimes", get(showtimes))
         .route("/movie/:id", get(movie))
         .nest("/account", account_routes)
+        .nest("/admin", admin_routes)
         .nest("/seating", seating_routes)
         .nest("/purchase", purchase_routes)
         .nest_service("/images", get_service(ServeDir::new("images")))
+        .layer(middleware::from_fn_with_state(state.clone(), verify_csrf))
+        .layer(middleware::from_fn(error_pages))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            rotate_cookie_keys,
+        ))
+        .layer(middleware::from_fn(trace_requests))
         .with_state(state);
 
-    println!("Listening on http://{ADDR}");
+    tracing::info!("listening on http://{addr}");
 
-    axum::Server::bind(&ADDR.parse().unwrap())
-        .serve(app.into_make_service())
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
     Ok(())
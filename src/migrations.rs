@@ -0,0 +1,132 @@
+use anyhow::{bail, Context};
+
+use crate::DB;
+
+struct Migration {
+    version: i64,
+    name: &'static str,
+    sql: &'static str,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("../migrations/0001_initial_schema.surql"),
+    },
+    Migration {
+        version: 2,
+        name: "ticket_pricing",
+        sql: include_str!("../migrations/0002_ticket_pricing.surql"),
+    },
+    Migration {
+        version: 3,
+        name: "orders",
+        sql: include_str!("../migrations/0003_orders.surql"),
+    },
+    Migration {
+        version: 4,
+        name: "seat_holds",
+        sql: include_str!("../migrations/0004_seat_holds.surql"),
+    },
+    Migration {
+        version: 5,
+        name: "seat_layouts",
+        sql: include_str!("../migrations/0005_seat_layouts.surql"),
+    },
+    Migration {
+        version: 6,
+        name: "payments",
+        sql: include_str!("../migrations/0006_payments.surql"),
+    },
+    Migration {
+        version: 7,
+        name: "redact_card_numbers",
+        sql: include_str!("../migrations/0007_redact_card_numbers.surql"),
+    },
+    Migration {
+        version: 8,
+        name: "cancellations",
+        sql: include_str!("../migrations/0008_cancellations.surql"),
+    },
+    Migration {
+        version: 9,
+        name: "password_resets",
+        sql: include_str!("../migrations/0009_password_resets.surql"),
+    },
+    Migration {
+        version: 10,
+        name: "email_verification",
+        sql: include_str!("../migrations/0010_email_verification.surql"),
+    },
+    Migration {
+        version: 11,
+        name: "roles",
+        sql: include_str!("../migrations/0011_roles.surql"),
+    },
+];
+
+fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+async fn applied_version() -> anyhow::Result<i64> {
+    let mut query = DB
+        .query("SELECT VALUE version FROM migrations ORDER BY version DESC LIMIT 1")
+        .await?;
+    let versions: Vec<i64> = query.take(0)?;
+    Ok(versions.into_iter().next().unwrap_or(0))
+}
+
+pub async fn check_schema() -> anyhow::Result<i64> {
+    let applied = applied_version().await?;
+    let latest = latest_version();
+    if applied > latest {
+        bail!("database schema is at version {applied} but this build only knows up to {latest}");
+    }
+    Ok(applied)
+}
+
+pub async fn require_current_schema() -> anyhow::Result<()> {
+    let applied = check_schema().await?;
+    if applied < latest_version() {
+        bail!(
+            "database schema is at version {applied}, run the `migrate` command to upgrade it to {}",
+            latest_version()
+        );
+    }
+    Ok(())
+}
+
+pub async fn run_migrations() -> anyhow::Result<()> {
+    let applied = check_schema().await?;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > applied) {
+        let sql = format!(
+            r#"
+            BEGIN TRANSACTION;
+            {}
+            CREATE type::thing("migrations", $version)
+                SET version = $version, name = $name, applied_at = time::now();
+            COMMIT TRANSACTION;
+            "#,
+            migration.sql
+        );
+        DB.query(sql)
+            .bind(("version", migration.version))
+            .bind(("name", migration.name))
+            .await
+            .and_then(|response| response.check())
+            .with_context(|| {
+                format!(
+                    "migration {:04}_{} failed",
+                    migration.version, migration.name
+                )
+            })?;
+        tracing::info!(
+            "applied migration {:04}_{}",
+            migration.version,
+            migration.name
+        );
+    }
+    Ok(())
+}
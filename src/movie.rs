@@ -1,9 +1,9 @@
 use askama::Template;
-use axum::{extract::Path, http::StatusCode, response::Result};
+use axum::extract::Path;
 use serde::{Deserialize, Serialize};
 use surrealdb::sql::Thing;
 
-use crate::DB;
+use crate::{db::Timed, error::AppError, DB};
 
 #[derive(Template)]
 #[template(path = "movie_page.html")]
@@ -41,16 +41,15 @@ struct Cast {
     director: String,
 }
 
-pub async fn movie(Path(id): Path<String>) -> Result<MovieAbout> {
+pub async fn movie(Path(id): Path<String>) -> Result<MovieAbout, AppError> {
     let Some((_, showtime_id)) = id.split_once(':') else {
-        return Err(StatusCode::NOT_ACCEPTABLE.into());
+        return Err(AppError::BadRequest(format!("{id} is not a movie")));
     };
-    let Ok(Some(movie)): surrealdb::Result<Option<Movie>> =
-        DB.select(("movies", showtime_id)).await
-    else {
-        return Err(StatusCode::NOT_ACCEPTABLE.into());
+    let movie: Option<Movie> = DB.select(("movies", showtime_id)).timed().await?;
+    let Some(movie) = movie else {
+        return Err(AppError::NotFound);
     };
-    let query = DB
+    let mut query = DB
         .query(
             r#"
             SELECT (SELECT (->people.name)[0] AS name, role FROM ->star) AS stars, 
@@ -61,19 +60,17 @@ pub async fn movie(Path(id): Path<String>) -> Result<MovieAbout> {
             "#,
         )
         .bind(("id", showtime_id))
-        .await;
-    let Ok(mut query) = query else {
-        return Err(StatusCode::NOT_ACCEPTABLE.into());
-    };
+        .timed()
+        .await?;
 
-    let Ok(Some(Cast {
+    let Some(Cast {
         actors,
         stars,
         writers,
         director,
-    })) = query.take(0)
+    }) = query.take(0)?
     else {
-        return Err(StatusCode::NOT_ACCEPTABLE.into());
+        return Err(AppError::NotFound);
     };
 
     Ok(MovieAbout {
@@ -0,0 +1,36 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+
+pub enum Verified {
+    Valid,
+    Legacy,
+    Invalid,
+}
+
+pub async fn hash_password(password: String) -> anyhow::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|err| anyhow::anyhow!("failed to hash password: {err}"))?;
+        Ok(hash.to_string())
+    })
+    .await?
+}
+
+// accounts created before passwords were hashed still hold the plaintext,
+// those are reported as Legacy so the caller can rehash them
+pub async fn verify_password(password: String, stored: String) -> Verified {
+    let result = tokio::task::spawn_blocking(move || match PasswordHash::new(&stored) {
+        Ok(hash) => match Argon2::default().verify_password(password.as_bytes(), &hash) {
+            Ok(()) => Verified::Valid,
+            Err(_) => Verified::Invalid,
+        },
+        Err(_) if !stored.is_empty() && stored == password => Verified::Legacy,
+        Err(_) => Verified::Invalid,
+    })
+    .await;
+    result.unwrap_or(Verified::Invalid)
+}
@@ -0,0 +1,238 @@
+use std::{collections::HashMap, fmt, future::Future, sync::Arc, time::Duration};
+
+use axum::async_trait;
+use surrealdb::sql::Id;
+use tokio::sync::Mutex;
+
+use crate::{
+    card::Brand,
+    config::{MockOutcome, MockPaymentConfig, PaymentConfig, PaymentProviderKind},
+};
+
+pub struct Card {
+    pub number: String,
+    pub exp_date: String,
+    pub cvv: String,
+}
+
+impl Card {
+    pub fn digits(&self) -> String {
+        self.number.chars().filter(char::is_ascii_digit).collect()
+    }
+}
+
+// what is kept of a card once it has been authorized
+#[derive(Debug)]
+pub struct Authorization {
+    pub reference: String,
+    pub brand: String,
+    pub last4: String,
+}
+
+#[derive(Debug)]
+pub enum PaymentError {
+    Declined(String),
+    Timeout,
+    Failed(String),
+}
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentError::Declined(reason) => write!(f, "declined: {reason}"),
+            PaymentError::Timeout => f.write_str("timed out"),
+            PaymentError::Failed(reason) => write!(f, "failed: {reason}"),
+        }
+    }
+}
+
+impl PaymentError {
+    // what the customer is told, never the processor's own wording
+    pub fn message(&self) -> &'static str {
+        match self {
+            PaymentError::Declined(_) => "Your card was declined. Please use another card.",
+            PaymentError::Timeout => {
+                "The payment could not be completed in time. You have not been charged."
+            }
+            PaymentError::Failed(_) => {
+                "The payment could not be completed. You have not been charged."
+            }
+        }
+    }
+}
+
+// amounts are in cents, references are the processor's id for an authorization
+#[async_trait]
+pub trait PaymentProvider: Send + Sync {
+    async fn authorize(&self, card: &Card, amount: u32) -> Result<Authorization, PaymentError>;
+    async fn capture(&self, reference: &str, amount: u32) -> Result<(), PaymentError>;
+    async fn void(&self, reference: &str) -> Result<(), PaymentError>;
+    async fn refund(&self, reference: &str, amount: u32) -> Result<(), PaymentError>;
+}
+
+#[derive(Clone)]
+pub struct Payments {
+    provider: Arc<dyn PaymentProvider>,
+    timeout: Duration,
+}
+
+impl Payments {
+    pub fn new(config: &PaymentConfig) -> Payments {
+        let provider: Arc<dyn PaymentProvider> = match config.provider {
+            PaymentProviderKind::Mock => Arc::new(MockProvider::new(config.mock.clone())),
+        };
+        Payments {
+            provider,
+            timeout: Duration::from_millis(config.timeout_ms),
+        }
+    }
+
+    pub async fn authorize(&self, card: &Card, amount: u32) -> Result<Authorization, PaymentError> {
+        self.limit(self.provider.authorize(card, amount)).await
+    }
+
+    pub async fn capture(&self, reference: &str, amount: u32) -> Result<(), PaymentError> {
+        self.limit(self.provider.capture(reference, amount)).await
+    }
+
+    pub async fn void(&self, reference: &str) -> Result<(), PaymentError> {
+        self.limit(self.provider.void(reference)).await
+    }
+
+    pub async fn refund(&self, reference: &str, amount: u32) -> Result<(), PaymentError> {
+        self.limit(self.provider.refund(reference, amount)).await
+    }
+
+    async fn limit<T>(
+        &self,
+        call: impl Future<Output = Result<T, PaymentError>>,
+    ) -> Result<T, PaymentError> {
+        let result = tokio::time::timeout(self.timeout, call)
+            .await
+            .unwrap_or(Err(PaymentError::Timeout));
+        if let Err(err) = &result {
+            tracing::warn!("payment {err}");
+        }
+        result
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MockState {
+    Authorized(u32),
+    // what is left to refund
+    Captured(u32),
+    Voided,
+    Refunded,
+}
+
+// approves, declines or hangs according to the config, and keeps track of
+// what happened to each authorization so misuse fails like a processor would
+pub struct MockProvider {
+    config: MockPaymentConfig,
+    payments: Mutex<HashMap<String, MockState>>,
+}
+
+impl MockProvider {
+    pub fn new(config: MockPaymentConfig) -> MockProvider {
+        MockProvider {
+            config,
+            payments: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn outcome(&self, card: &Card) -> MockOutcome {
+        let number = card.digits();
+        if card.exp_date.is_empty()
+            || card.cvv.is_empty()
+            || self.config.decline_cards.contains(&number)
+        {
+            MockOutcome::Decline
+        } else if self.config.timeout_cards.contains(&number) {
+            MockOutcome::Timeout
+        } else {
+            self.config.outcome
+        }
+    }
+
+    async fn respond(&self, outcome: MockOutcome) -> Result<(), PaymentError> {
+        tokio::time::sleep(Duration::from_millis(self.config.latency_ms)).await;
+        match outcome {
+            MockOutcome::Approve => Ok(()),
+            MockOutcome::Decline => Err(PaymentError::Declined("mock decline".to_string())),
+            MockOutcome::Timeout => std::future::pending().await,
+        }
+    }
+
+    async fn transition(
+        &self,
+        reference: &str,
+        change: impl FnOnce(MockState) -> Option<MockState>,
+    ) -> Result<(), PaymentError> {
+        self.respond(MockOutcome::Approve).await?;
+        let mut payments = self.payments.lock().await;
+        // state is only kept in memory, so payments made before a restart are
+        // taken to be captured with no limit on what can be refunded
+        let state = match payments.get(reference) {
+            Some(state) => *state,
+            None if reference.starts_with("mock_") => MockState::Captured(u32::MAX),
+            None => return Err(PaymentError::Failed(format!("unknown payment {reference}"))),
+        };
+        let Some(next) = change(state) else {
+            return Err(PaymentError::Failed(format!(
+                "payment {reference} is {state:?}"
+            )));
+        };
+        payments.insert(reference.to_string(), next);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for MockProvider {
+    async fn authorize(&self, card: &Card, amount: u32) -> Result<Authorization, PaymentError> {
+        self.respond(self.outcome(card)).await?;
+        let reference = format!("mock_{}", Id::rand().to_raw());
+        self.payments
+            .lock()
+            .await
+            .insert(reference.clone(), MockState::Authorized(amount));
+        let digits = card.digits();
+        Ok(Authorization {
+            reference,
+            brand: Brand::detect(&digits)
+                .map_or("unknown", Brand::name)
+                .to_string(),
+            last4: digits[digits.len().saturating_sub(4)..].to_string(),
+        })
+    }
+
+    async fn capture(&self, reference: &str, amount: u32) -> Result<(), PaymentError> {
+        self.transition(reference, |state| match state {
+            MockState::Authorized(authorized) if amount <= authorized => {
+                Some(MockState::Captured(amount))
+            }
+            _ => None,
+        })
+        .await
+    }
+
+    async fn void(&self, reference: &str) -> Result<(), PaymentError> {
+        self.transition(reference, |state| match state {
+            MockState::Authorized(_) => Some(MockState::Voided),
+            _ => None,
+        })
+        .await
+    }
+
+    async fn refund(&self, reference: &str, amount: u32) -> Result<(), PaymentError> {
+        self.transition(reference, |state| match state {
+            MockState::Captured(captured) if amount < captured => {
+                Some(MockState::Captured(captured - amount))
+            }
+            MockState::Captured(captured) if amount == captured => Some(MockState::Refunded),
+            _ => None,
+        })
+        .await
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::PricingConfig, db::Timed, error::AppError, DB};
+
+const DEFAULT_FORMAT: &str = "standard";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TicketType {
+    #[default]
+    Adult,
+    Child,
+    Senior,
+    Student,
+}
+
+impl TicketType {
+    pub const ALL: [TicketType; 4] = [
+        TicketType::Adult,
+        TicketType::Child,
+        TicketType::Senior,
+        TicketType::Student,
+    ];
+
+    pub fn value(self) -> &'static str {
+        match self {
+            TicketType::Adult => "adult",
+            TicketType::Child => "child",
+            TicketType::Senior => "senior",
+            TicketType::Student => "student",
+        }
+    }
+}
+
+impl fmt::Display for TicketType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TicketType::Adult => "Adult",
+            TicketType::Child => "Child",
+            TicketType::Senior => "Senior",
+            TicketType::Student => "Student",
+        };
+        f.write_str(name)
+    }
+}
+
+// what the price of a seat depends on, read from the showtime and its theater
+#[derive(Debug, Deserialize)]
+pub struct Showing {
+    pub hour: u32,
+    pub day: i32,
+    pub format: Option<String>,
+}
+
+impl Showing {
+    pub fn is_matinee(&self, config: &PricingConfig) -> bool {
+        self.hour < config.matinee_before
+    }
+
+    pub fn is_weekend(&self) -> bool {
+        self.day == 6 || self.day == 7
+    }
+
+    pub fn format(&self) -> &str {
+        self.format.as_deref().unwrap_or(DEFAULT_FORMAT)
+    }
+}
+
+pub async fn showing(showtime: &str) -> Result<Showing, AppError> {
+    let mut query = DB
+        .query(
+            r#"
+            SELECT time::hour(time) AS hour, day,
+            (<-showing<-theaters.format)[0] AS format
+            FROM ONLY type::thing("showtime", $id)
+            "#,
+        )
+        .bind(("id", showtime))
+        .timed()
+        .await?;
+    let Some(showing) = query.take(0)? else {
+        return Err(AppError::NotFound);
+    };
+    Ok(showing)
+}
+
+pub fn price(config: &PricingConfig, ticket: TicketType, showing: &Showing) -> u32 {
+    let mut price = match ticket {
+        TicketType::Adult => config.adult,
+        TicketType::Child => config.child,
+        TicketType::Senior => config.senior,
+        TicketType::Student => config.student,
+    };
+    if showing.is_matinee(config) {
+        price = price.saturating_sub(config.matinee_discount);
+    }
+    if showing.is_weekend() {
+        price += config.weekend_surcharge;
+    }
+    price + config.formats.get(showing.format()).copied().unwrap_or(0)
+}
+
+pub fn format_price(cents: u32) -> String {
+    format!("${}.{:02}", cents / 100, cents % 100)
+}
+
+pub struct TicketPrice {
+    pub ticket: TicketType,
+    pub price: String,
+}
+
+pub fn price_list(config: &PricingConfig, showing: &Showing) -> Vec<TicketPrice> {
+    TicketType::ALL
+        .into_iter()
+        .map(|ticket| TicketPrice {
+            ticket,
+            price: format_price(price(config, ticket, showing)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn showing(hour: u32, day: i32, format: Option<&str>) -> Showing {
+        Showing {
+            hour,
+            day,
+            format: format.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn evening_weekday_is_the_base_price() {
+        let config = PricingConfig::default();
+        let evening = showing(19, 3, None);
+        assert_eq!(price(&config, TicketType::Adult, &evening), config.adult);
+        assert_eq!(price(&config, TicketType::Child, &evening), config.child);
+        assert_eq!(price(&config, TicketType::Senior, &evening), config.senior);
+        assert_eq!(
+            price(&config, TicketType::Student, &evening),
+            config.student
+        );
+    }
+
+    #[test]
+    fn matinees_are_discounted_until_the_cutoff() {
+        let config = PricingConfig::default();
+        let before = showing(config.matinee_before - 1, 3, None);
+        let at = showing(config.matinee_before, 3, None);
+        assert_eq!(
+            price(&config, TicketType::Adult, &before),
+            config.adult - config.matinee_discount
+        );
+        assert_eq!(price(&config, TicketType::Adult, &at), config.adult);
+    }
+
+    #[test]
+    fn discounts_never_go_below_zero() {
+        let config = PricingConfig {
+            child: 100,
+            matinee_discount: 300,
+            ..PricingConfig::default()
+        };
+        assert_eq!(price(&config, TicketType::Child, &showing(10, 3, None)), 0);
+    }
+
+    #[test]
+    fn weekends_and_formats_add_to_the_price() {
+        let config = PricingConfig::default();
+        let saturday = showing(19, 6, None);
+        let sunday = showing(19, 7, None);
+        let friday = showing(19, 5, None);
+        let imax = showing(19, 3, Some("imax"));
+        assert_eq!(
+            price(&config, TicketType::Adult, &saturday),
+            config.adult + config.weekend_surcharge
+        );
+        assert_eq!(
+            price(&config, TicketType::Adult, &sunday),
+            config.adult + config.weekend_surcharge
+        );
+        assert_eq!(price(&config, TicketType::Adult, &friday), config.adult);
+        assert_eq!(
+            price(&config, TicketType::Adult, &imax),
+            config.adult + config.formats["imax"]
+        );
+        assert_eq!(
+            price(&config, TicketType::Adult, &showing(19, 3, Some("unknown"))),
+            config.adult
+        );
+    }
+
+    #[test]
+    fn formats_cents_as_dollars() {
+        assert_eq!(format_price(0), "$0.00");
+        assert_eq!(format_price(905), "$9.05");
+        assert_eq!(format_price(1250), "$12.50");
+    }
+}
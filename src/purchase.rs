@@ -1,29 +1,62 @@
-use crate::DB;
+use std::{collections::HashSet, fmt::Write, iter, sync::Arc};
+
+use crate::{
+    auth::VerifiedUser,
+    card::{card_number, is_valid_cvv, is_valid_expiry, Brand},
+    config::{Config, PricingConfig},
+    db::Timed,
+    error::AppError,
+    mail::{Email, Mailer},
+    payment::{Card, Payments},
+    pricing::{format_price, price, price_list, showing, TicketPrice, TicketType},
+    seatmap::{check_bookable, showtime_layout},
+    DB,
+};
 use askama::Template;
 use askama_axum::IntoResponse;
 use axum::{
-    extract::{Form, Path},
-    http::StatusCode,
-    response::{Redirect, Response},
+    extract::{Path, State},
+    response::Response,
 };
-use axum_extra::extract::PrivateCookieJar;
+use axum_extra::extract::{Form, Query};
+use chrono::Utc;
+use image::{DynamicImage, ImageOutputFormat, Luma};
+use lettre::message::{header::ContentType, Attachment, MultiPart, SinglePart};
 use qrcode::render::svg;
 use qrcode::QrCode;
-use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use surrealdb::sql::{Id, Thing};
 
-#[derive(Template)]
-#[template(path = "unavailable.html")]
-pub struct Unavailable {}
+const MAX_SEATS: usize = 10;
 
 #[derive(Template)]
 #[template(path = "complete.html")]
 pub struct Complete {
     pub movie: String,
     pub time: String,
-    pub seat: i32,
+    pub order: Id,
+    pub tickets: Vec<PurchasedTicket>,
+    pub total: String,
+    pub card: String,
+}
+
+#[derive(Template)]
+#[template(path = "confirmation_email.html")]
+struct ConfirmationEmail<'a> {
+    complete: &'a Complete,
+}
+
+#[derive(Template)]
+#[template(path = "confirmation_email.txt")]
+struct ConfirmationText<'a> {
+    complete: &'a Complete,
+}
+
+pub struct PurchasedTicket {
+    pub label: String,
     pub ticket: Id,
+    pub ticket_type: TicketType,
+    pub price: String,
     pub svg: String,
 }
 
@@ -32,64 +65,129 @@ pub struct Complete {
 pub struct PurchasePage {
     pub id: String,
     pub time: String,
-    pub seat: i32,
     pub movie: String,
+    pub tickets: Vec<CartTicket>,
+    pub prices: Vec<TicketPrice>,
+    pub total: String,
     pub card_num: String,
     pub exp_date: String,
     pub cvv: String,
     pub valid_card_num: bool,
     pub valid_exp: bool,
     pub valid_cvv: bool,
+    pub payment_error: Option<&'static str>,
+}
+
+pub struct CartTicket {
+    pub seat: i32,
+    pub label: String,
+    pub ticket_type: TicketType,
+    pub price: u32,
+}
+
+impl CartTicket {
+    pub fn price_label(&self) -> String {
+        format_price(self.price)
+    }
 }
 
 impl PurchasePage {
-    fn new(id: String, time: String, seat: i32, movie: String) -> PurchasePage {
-        PurchasePage {
+    async fn new(
+        config: &PricingConfig,
+        id: String,
+        tickets: Vec<(i32, TicketType)>,
+    ) -> Result<PurchasePage, AppError> {
+        let MovieTime { movie, time } = movie_time(&id).await?;
+        let showing = showing(&id).await?;
+        let layout = showtime_layout(&id).await?;
+        let tickets: Vec<CartTicket> = tickets
+            .into_iter()
+            .map(|(seat, ticket_type)| CartTicket {
+                seat,
+                label: layout.label(seat),
+                ticket_type,
+                price: price(config, ticket_type, &showing),
+            })
+            .collect();
+        let total = format_price(tickets.iter().map(|ticket| ticket.price).sum());
+        Ok(PurchasePage {
             id,
             time,
-            seat,
             movie,
+            tickets,
+            prices: price_list(config, &showing),
+            total,
             card_num: String::new(),
             exp_date: String::new(),
             cvv: String::new(),
             valid_card_num: true,
             valid_cvv: true,
             valid_exp: true,
+            payment_error: None,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Cart {
+    #[serde(default)]
+    pub seats: Vec<i32>,
+    #[serde(default)]
+    pub ticket_types: Vec<TicketType>,
+}
+
+impl Cart {
+    // pairs each seat with its ticket type, seats without one are adult tickets
+    pub fn tickets(self) -> Result<Vec<(i32, TicketType)>, AppError> {
+        if self.seats.is_empty() {
+            return Err(AppError::BadRequest(
+                "Select at least one seat.".to_string(),
+            ));
         }
+        if self.seats.len() > MAX_SEATS {
+            return Err(AppError::BadRequest(format!(
+                "At most {MAX_SEATS} seats can be bought at once."
+            )));
+        }
+        let mut seen = HashSet::new();
+        if !self.seats.iter().all(|seat| seen.insert(*seat)) {
+            return Err(AppError::BadRequest(
+                "A seat was selected more than once.".to_string(),
+            ));
+        }
+        let ticket_types = self
+            .ticket_types
+            .into_iter()
+            .chain(iter::repeat(TicketType::Adult));
+        Ok(self.seats.into_iter().zip(ticket_types).collect())
     }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct UserInfo {
+    #[serde(default)]
+    pub seats: Vec<i32>,
+    #[serde(default)]
+    pub ticket_types: Vec<TicketType>,
     pub card_num: String,
     pub exp_date: String,
     pub cvv: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ShowtimeSeat {
-    pub id: Thing,
-    pub seat: i32,
-    pub time: String,
-}
-
 #[derive(Debug, Deserialize)]
 struct MovieTime {
     movie: String,
     time: String,
 }
 
-#[derive(Deserialize)]
-struct Record {
-    #[allow(dead_code)]
-    id: Thing,
+#[derive(Debug, Deserialize)]
+struct Order {
+    order: Thing,
+    tickets: Vec<Thing>,
 }
 
-pub async fn purchase(jar: PrivateCookieJar, Path((id, seat)): Path<(String, i32)>) -> Response {
-    if let Err(err) = check_session(&jar).await {
-        return err;
-    }
-    let query = DB
+async fn movie_time(id: &str) -> Result<MovieTime, AppError> {
+    let mut query = DB
         .query(
             r#"
             SELECT (<-showing<-theaters<-playing<-movies.name)[0] AS movie, 
@@ -97,147 +195,306 @@ pub async fn purchase(jar: PrivateCookieJar, Path((id, seat)): Path<(String, i32
             FROM ONLY type::thing("showtime", $id)
             "#,
         )
-        .bind(("id", &id))
-        .await;
-    let Ok(mut query) = query else {
-        return StatusCode::NOT_ACCEPTABLE.into_response();
-    };
-    let Ok(Some(MovieTime { movie, time })) = query.take(0) else {
-        return StatusCode::NOT_ACCEPTABLE.into_response();
+        .bind(("id", id))
+        .timed()
+        .await?;
+    let Some(movie_time) = query.take(0)? else {
+        return Err(AppError::NotFound);
     };
+    Ok(movie_time)
+}
 
-    PurchasePage::new(id, time, seat, movie).into_response()
+pub async fn purchase(
+    _: VerifiedUser,
+    State(config): State<Arc<Config>>,
+    Path(id): Path<String>,
+    Query(cart): Query<Cart>,
+) -> Result<PurchasePage, AppError> {
+    PurchasePage::new(&config.pricing, id, cart.tickets()?).await
 }
 
 pub async fn complete_purchase(
-    jar: PrivateCookieJar,
-    Path((id, seat, movie, time)): Path<(String, i32, String, String)>,
+    VerifiedUser(user): VerifiedUser,
+    State(config): State<Arc<Config>>,
+    State(payments): State<Payments>,
+    State(mailer): State<Mailer>,
+    Path(id): Path<String>,
     Form(UserInfo {
+        seats,
+        ticket_types,
         card_num,
         exp_date,
         cvv,
     }): Form<UserInfo>,
-) -> Response {
-    let jar = match check_session(&jar).await {
-        Ok(e) => e,
-        Err(err) => return err,
-    };
+) -> Result<Response, AppError> {
+    let tickets = Cart {
+        seats,
+        ticket_types,
+    }
+    .tickets()?;
 
-    let valid_card_num = is_valid_card_number(&card_num);
-    let valid_cvv = is_valid_cvv(&cvv);
-    let valid_exp = is_valid_exp(&exp_date);
-    if !valid_card_num || !valid_cvv || !valid_exp {
-        return PurchasePage {
-            id,
-            time,
-            seat,
-            movie,
+    let number = card_number(&card_num);
+    let valid_card_num = number.is_some();
+    let valid_cvv = is_valid_cvv(&cvv, number.as_ref().map(|(_, brand)| *brand));
+    let valid_exp = is_valid_expiry(&exp_date, Utc::now().date_naive());
+    let (Some((digits, _)), true, true) = (number, valid_cvv, valid_exp) else {
+        return Ok(PurchasePage {
             card_num,
             exp_date,
             cvv,
             valid_card_num,
             valid_cvv,
             valid_exp,
+            ..PurchasePage::new(&config.pricing, id, tickets).await?
         }
-        .into_response();
-    }
-
-    let Some(session) = jar.get("session") else {
-        return StatusCode::NOT_ACCEPTABLE.into_response();
+        .into_response());
     };
 
-    let query= DB
-            .query(
-                r#"
-                BEGIN TRANSACTION;
-                LET $user = SELECT VALUE (->account_session->accounts)[0]  
-                FROM ONLY type::thing("sessions", $session_id);
+    let seat_nums: Vec<i32> = tickets.iter().map(|(seat, _)| *seat).collect();
+    let layout = showtime_layout(&id).await?;
+    check_bookable(&layout, &seat_nums)?;
 
-                LET $seat = SELECT VALUE ->showtime_seat->(seats WHERE seat = $seat_num AND available = true).*
-                FROM ONLY type::thing("showtime", $showtime);
+    let MovieTime { movie, time } = movie_time(&id).await?;
+    let showing = showing(&id).await?;
+    let prices: Vec<u32> = tickets
+        .iter()
+        .map(|(_, ticket_type)| price(&config.pricing, *ticket_type, &showing))
+        .collect();
+    let total: u32 = prices.iter().sum();
 
-                UPDATE $seat SET available = false;
+    let card = Card {
+        number: digits,
+        exp_date: exp_date.trim().to_string(),
+        cvv: cvv.trim().to_string(),
+    };
+    let authorization = match payments.authorize(&card, total).await {
+        Ok(authorization) => authorization,
+        Err(err) => {
+            return Ok(PurchasePage {
+                card_num,
+                exp_date,
+                payment_error: Some(err.message()),
+                ..PurchasePage::new(&config.pricing, id, tickets).await?
+            }
+            .into_response())
+        }
+    };
 
-                RELATE ONLY $user->purchase->$seat SET time = time::now(), card_number = $card_number, exp_date = $exp_date RETURN VALUE id;
+    // every seat is reserved in the one transaction, if any of them is gone
+    // the THROW cancels all of it
+    let mut sql = String::from(
+        r#"
+        BEGIN TRANSACTION;
 
-                COMMIT TRANSACTION                
-                "#,
-            )
-            .bind(("session_id", session.value()))
-            .bind(("seat_num", seat))
-            .bind(("showtime", id))
-            .bind(("card_number", &card_num))
-            .bind(("exp_date", &exp_date))
-            .await;
+        LET $seats = SELECT VALUE ->showtime_seat->(seats WHERE seat INSIDE $seat_nums AND available = true
+            AND (held_until = NONE OR held_until <= time::now() OR held_by = $holder))
+        FROM ONLY type::thing("showtime", $showtime);
+        IF array::len($seats) != array::len($seat_nums) {
+            THROW "seat unavailable";
+        };
+        UPDATE $seats SET available = false, held_by = NONE, held_until = NONE;
 
-    let Ok(mut query) = query else {
-        return StatusCode::NOT_ACCEPTABLE.into_response();
-    };
+        LET $order = CREATE ONLY orders
+            SET account = $user, time = time::now(), total = $total,
+            payment_reference = $payment_reference, payment_status = "authorized",
+            card_brand = $card_brand, card_last4 = $card_last4;
+        "#,
+    );
+    let mut returned = Vec::new();
+    for i in 0..tickets.len() {
+        write!(
+            sql,
+            r#"
+        LET $seat{i} = (SELECT VALUE id FROM $seats WHERE seat = $seat_num{i})[0];
+        LET $ticket{i} = RELATE ONLY $user->purchase->$seat{i}
+            SET time = time::now(), order = $order.id, ticket_type = $ticket_type{i}, price = $price{i}
+            RETURN VALUE id;
+            "#
+        )
+        .unwrap();
+        returned.push(format!("$ticket{i}"));
+    }
+    write!(
+        sql,
+        r#"
+        RETURN {{ order: $order.id, tickets: [{}] }};
 
-    let Ok(result) = query.take(3) else {
-        return Unavailable {}.into_response();
+        COMMIT TRANSACTION;
+        "#,
+        returned.join(", ")
+    )
+    .unwrap();
+
+    let mut query = DB
+        .query(sql)
+        .bind(("user", &user.account))
+        .bind(("holder", Thing::from(("sessions", user.session.as_str()))))
+        .bind(("showtime", &id))
+        .bind(("seat_nums", &seat_nums))
+        .bind(("total", total))
+        .bind(("payment_reference", &authorization.reference))
+        .bind(("card_brand", &authorization.brand))
+        .bind(("card_last4", &authorization.last4));
+    for (i, ((seat, ticket_type), price)) in tickets.iter().zip(&prices).enumerate() {
+        query = query
+            .bind((format!("seat_num{i}"), *seat))
+            .bind((format!("ticket_type{i}"), *ticket_type))
+            .bind((format!("price{i}"), *price));
+    }
+    let query = match query.timed().await {
+        Ok(query) => query,
+        Err(err) => {
+            let _ = payments.void(&authorization.reference).await;
+            return Err(err.into());
+        }
     };
 
-    let Some(ticket): Option<Thing> = result else {
-        return Unavailable {}.into_response();
+    let last = query.num_statements() - 1;
+    let created = check_seats(query).and_then(|mut query| {
+        query
+            .take::<Option<Order>>(last)?
+            .ok_or_else(|| AppError::Internal(anyhow::anyhow!("order not created")))
+    });
+    let Order {
+        order,
+        tickets: ids,
+    } = match created {
+        Ok(order) => order,
+        Err(err) => {
+            let _ = payments.void(&authorization.reference).await;
+            return Err(err);
+        }
     };
 
-    let code = QrCode::new(ticket.id.to_string().as_bytes()).unwrap();
-    let svg = code
-        .render()
-        .min_dimensions(400, 400)
-        .dark_color(svg::Color("#000000"))
-        .light_color(svg::Color("#ffffff"))
-        .build();
+    // the seats are only paid for once they are ours, if the charge fails
+    // they go back on sale
+    if let Err(err) = payments.capture(&authorization.reference, total).await {
+        let _ = payments.void(&authorization.reference).await;
+        cancel_order(&order).await?;
+        return Ok(PurchasePage {
+            card_num,
+            exp_date,
+            payment_error: Some(err.message()),
+            ..PurchasePage::new(&config.pricing, id, tickets).await?
+        }
+        .into_response());
+    }
+    DB.query(r#"UPDATE $order SET payment_status = "captured""#)
+        .bind(("order", &order))
+        .timed()
+        .await?
+        .check()?;
+
+    let tickets = tickets
+        .into_iter()
+        .zip(prices)
+        .zip(ids)
+        .map(|(((seat, ticket_type), price), ticket)| PurchasedTicket {
+            label: layout.label(seat),
+            svg: qr_code(&ticket.id.to_string()),
+            ticket: ticket.id,
+            ticket_type,
+            price: format_price(price),
+        })
+        .collect();
 
-    Complete {
+    let complete = Complete {
         movie,
         time,
-        seat,
-        ticket: ticket.id,
-        svg,
+        order: order.id,
+        tickets,
+        total: format_price(total),
+        card: card_label(&authorization.brand, &authorization.last4),
+    };
+    match confirmation_email(&user.email, &complete) {
+        Ok(email) => mailer.send_later(email),
+        Err(err) => tracing::error!("failed to render confirmation email: {err}"),
     }
-    .into_response()
+    Ok(complete.into_response())
 }
 
-fn is_valid_exp(exp: &String) -> bool {
-    let exp_pattern = Regex::new(r"^(0[1-9]|1[0-2])/\d{2}$").unwrap();
-    exp_pattern.is_match(exp)
+// each ticket's QR code goes in as an inline PNG the HTML refers to by cid
+fn confirmation_email(to: &str, complete: &Complete) -> askama::Result<Email> {
+    let mut html =
+        MultiPart::related().singlepart(SinglePart::html(ConfirmationEmail { complete }.render()?));
+    for ticket in &complete.tickets {
+        let id = ticket.ticket.to_string();
+        html = html.singlepart(
+            Attachment::new_inline(id.clone())
+                .body(qr_png(&id), ContentType::parse("image/png").unwrap()),
+        );
+    }
+    Ok(Email {
+        to: to.to_string(),
+        subject: format!("Your tickets for {}", complete.movie),
+        body: MultiPart::alternative()
+            .singlepart(SinglePart::plain(ConfirmationText { complete }.render()?))
+            .multipart(html),
+    })
 }
 
-fn is_valid_card_number(card_num: &String) -> bool {
-    if card_num.len() != 16 {
-        return false;
+fn card_label(brand: &str, last4: &str) -> String {
+    match Brand::from_name(brand) {
+        Some(brand) => format!("{brand} ending in {last4}"),
+        None => format!("Card ending in {last4}"),
     }
-    for ch in card_num.chars() {
-        if !ch.is_numeric() {
-            return false;
-        }
-    }
-    true
 }
 
-fn is_valid_cvv(cvv: &String) -> bool {
-    if cvv.len() != 3 {
-        return false;
+async fn cancel_order(order: &Thing) -> Result<(), AppError> {
+    DB.query(
+        r#"
+        BEGIN TRANSACTION;
+
+        LET $seats = SELECT VALUE out FROM purchase WHERE order = $order;
+        UPDATE $seats SET available = true;
+        DELETE purchase WHERE order = $order;
+        DELETE $order;
+
+        COMMIT TRANSACTION;
+        "#,
+    )
+    .bind(("order", order))
+    .timed()
+    .await?
+    .check()?;
+    Ok(())
+}
+
+// a failed transaction fails every statement, only the one that threw says
+// the seats were taken
+pub fn check_seats(mut response: surrealdb::Response) -> Result<surrealdb::Response, AppError> {
+    let mut errors: Vec<_> = response.take_errors().into_iter().collect();
+    errors.sort_by_key(|(statement, _)| *statement);
+    if errors
+        .iter()
+        .any(|(_, err)| err.to_string().contains("seat unavailable"))
+    {
+        return Err(AppError::Conflict(
+            "One of the seats you've chosen is already taken. Please select other seats."
+                .to_string(),
+        ));
     }
-    for ch in cvv.chars() {
-        if !ch.is_numeric() {
-            return false;
-        }
+    match errors.into_iter().next() {
+        Some((_, err)) => Err(err.into()),
+        None => Ok(response),
     }
-    true
 }
 
-async fn check_session(jar: &PrivateCookieJar) -> Result<&PrivateCookieJar, Response> {
-    let Some(session) = jar.get("session") else {
-        return Err(Redirect::to("/login").into_response());
-    };
-    let Ok(Some(_)) = DB
-        .select::<Option<Record>>(("sessions", session.value()))
-        .await
-    else {
-        return Err(Redirect::to("/login").into_response());
-    };
-    Ok(jar)
+fn qr_code(data: &str) -> String {
+    let code = QrCode::new(data.as_bytes()).unwrap();
+    code.render()
+        .min_dimensions(400, 400)
+        .dark_color(svg::Color("#000000"))
+        .light_color(svg::Color("#ffffff"))
+        .build()
+}
+
+fn qr_png(data: &str) -> Vec<u8> {
+    let code = QrCode::new(data.as_bytes()).unwrap();
+    let image = code.render::<Luma<u8>>().min_dimensions(200, 200).build();
+    let mut png = Vec::new();
+    DynamicImage::ImageLuma8(image)
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .unwrap();
+    png
 }
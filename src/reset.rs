@@ -0,0 +1,261 @@
+use std::sync::Arc;
+
+use askama::Template;
+use askama_axum::IntoResponse;
+use axum::{
+    extract::{Form, Query, State},
+    response::Response,
+};
+use axum_extra::extract::cookie::{Cookie, Key, PrivateCookieJar};
+use axum_htmx::HxRequest;
+use chrono::{Duration, Utc};
+use lettre::message::MultiPart;
+use serde::Deserialize;
+use surrealdb::sql::{Datetime, Thing};
+
+use crate::{
+    auth::OptionalUser,
+    config::Config,
+    csrf::CsrfToken,
+    db::Timed,
+    error::AppError,
+    landing::Index,
+    login::{is_valid_email, is_valid_password, Login},
+    mail::{Email, Mailer},
+    password::hash_password,
+    tokens, DB,
+};
+
+const PURPOSE: &str = "password_reset";
+
+#[derive(Template)]
+#[template(path = "forgot_password.html")]
+pub struct ForgotPassword {
+    email: String,
+    valid_email: bool,
+    sent: bool,
+}
+
+#[derive(Template)]
+#[template(path = "reset_password.html")]
+pub struct ResetPassword {
+    token: String,
+    valid_password: bool,
+}
+
+#[derive(Template)]
+#[template(path = "reset_email.html")]
+struct ResetEmail<'a> {
+    link: &'a str,
+    minutes: u32,
+}
+
+#[derive(Template)]
+#[template(path = "reset_email.txt")]
+struct ResetText<'a> {
+    link: &'a str,
+    minutes: u32,
+}
+
+#[derive(Deserialize)]
+pub struct ForgotForm {
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct TokenQuery {
+    token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetForm {
+    token: String,
+    password: String,
+}
+
+pub async fn forgot_password() -> ForgotPassword {
+    ForgotPassword {
+        email: String::new(),
+        valid_email: true,
+        sent: false,
+    }
+}
+
+// answers the same whether or not the address has an account, so the form
+// can't be used to find out who has one
+pub async fn send_reset(
+    State(config): State<Arc<Config>>,
+    State(key): State<Key>,
+    State(mailer): State<Mailer>,
+    Form(ForgotForm { email }): Form<ForgotForm>,
+) -> Result<ForgotPassword, AppError> {
+    if !is_valid_email(&email) {
+        return Ok(ForgotPassword {
+            email,
+            valid_email: false,
+            sent: false,
+        });
+    }
+
+    let minutes = config.accounts.reset_minutes;
+    let expires = Utc::now() + Duration::minutes(minutes.into());
+    let mut query = DB
+        .query("SELECT VALUE id FROM ONLY accounts WHERE email = $email LIMIT 1")
+        .bind(("email", &email))
+        .timed()
+        .await?;
+    let account: Option<Thing> = query.take(0)?;
+
+    if let Some(account) = account {
+        let mut query = DB
+            .query(
+                r#"
+                BEGIN TRANSACTION;
+
+                UPDATE password_resets SET used_at = time::now() WHERE account = $account AND used_at = NONE;
+                CREATE ONLY password_resets SET account = $account, expires = $expires RETURN VALUE id;
+
+                COMMIT TRANSACTION;
+                "#,
+            )
+            .bind(("account", &account))
+            .bind(("expires", Datetime::from(expires)))
+            .timed()
+            .await?;
+        let Some(reset): Option<Thing> = query.take(1)? else {
+            return Err(AppError::Internal(anyhow::anyhow!(
+                "password reset not created"
+            )));
+        };
+        let token = tokens::sign(&key, PURPOSE, &reset.id.to_raw(), expires);
+        let link = format!(
+            "{}/reset_password?token={token}",
+            config.server.public_url.trim_end_matches('/')
+        );
+        let body = MultiPart::alternative_plain_html(
+            ResetText {
+                link: &link,
+                minutes,
+            }
+            .render()?,
+            ResetEmail {
+                link: &link,
+                minutes,
+            }
+            .render()?,
+        );
+        mailer.send_later(Email {
+            to: email.clone(),
+            subject: "Reset your password".to_string(),
+            body,
+        });
+    }
+
+    Ok(ForgotPassword {
+        email,
+        valid_email: true,
+        sent: true,
+    })
+}
+
+// the link in the email opens the whole site with the form in it
+pub async fn reset_password_page(
+    OptionalUser(user): OptionalUser,
+    CsrfToken(csrf): CsrfToken,
+    HxRequest(htmx): HxRequest,
+    State(key): State<Key>,
+    Query(TokenQuery { token }): Query<TokenQuery>,
+) -> Result<Response, AppError> {
+    if !htmx {
+        return Ok(Index {
+            logged_in: user.is_some(),
+            content: format!("/reset_password?token={token}"),
+            csrf,
+        }
+        .into_response());
+    }
+
+    let Some(reset) = tokens::verify(&key, PURPOSE, &token) else {
+        return Err(invalid_link());
+    };
+    let mut query = DB
+        .query(
+            r#"
+            SELECT VALUE id FROM ONLY type::thing("password_resets", $reset)
+            WHERE used_at = NONE AND expires > time::now()
+            "#,
+        )
+        .bind(("reset", reset))
+        .timed()
+        .await?;
+    let unused: Option<Thing> = query.take(0)?;
+    if unused.is_none() {
+        return Err(invalid_link());
+    }
+
+    Ok(ResetPassword {
+        token,
+        valid_password: true,
+    }
+    .into_response())
+}
+
+// using the link sets the password and logs the account out everywhere
+pub async fn reset_password(
+    jar: PrivateCookieJar,
+    State(key): State<Key>,
+    Form(ResetForm { token, password }): Form<ResetForm>,
+) -> Result<Response, AppError> {
+    let Some(reset) = tokens::verify(&key, PURPOSE, &token) else {
+        return Err(invalid_link());
+    };
+    if !is_valid_password(&password) {
+        return Ok(ResetPassword {
+            token,
+            valid_password: false,
+        }
+        .into_response());
+    }
+
+    let hash = hash_password(password).await?;
+    let response = DB
+        .query(
+            r#"
+            BEGIN TRANSACTION;
+
+            LET $used = UPDATE type::thing("password_resets", $reset) SET used_at = time::now()
+                WHERE used_at = NONE AND expires > time::now();
+            IF array::len($used) = 0 {
+                THROW "reset link already used";
+            };
+            LET $account = $used[0].account;
+            UPDATE $account SET password = $password;
+
+            LET $sessions = SELECT VALUE in FROM account_session WHERE out = $account;
+            DELETE account_session WHERE out = $account;
+            DELETE $sessions;
+
+            COMMIT TRANSACTION;
+            "#,
+        )
+        .bind(("reset", reset))
+        .bind(("password", hash))
+        .timed()
+        .await?;
+    if response.check().is_err() {
+        return Err(invalid_link());
+    }
+
+    let jar = jar.remove(Cookie::build("session", "").path("/").finish());
+    let login = Login {
+        notice: Some("Your password has been changed. Log in with your new password."),
+        ..Login::default()
+    };
+    Ok((jar, login).into_response())
+}
+
+fn invalid_link() -> AppError {
+    AppError::BadRequest(
+        "This password reset link is invalid or has expired. Please request a new one.".to_string(),
+    )
+}
@@ -0,0 +1,96 @@
+use std::{fmt, marker::PhantomData};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use axum_extra::extract::cookie::Key;
+use serde::{Deserialize, Serialize};
+
+use crate::{auth::CurrentUser, error::AppError};
+
+// ordered so that every role has the rights of the ones before it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    Customer,
+    BoxOffice,
+    Manager,
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Customer, Role::BoxOffice, Role::Manager, Role::Admin];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Customer => "customer",
+            Role::BoxOffice => "box_office",
+            Role::Manager => "manager",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Role> {
+        Role::ALL.into_iter().find(|role| role.name() == name)
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Customer => "Customer",
+            Role::BoxOffice => "Box office staff",
+            Role::Manager => "Manager",
+            Role::Admin => "Admin",
+        })
+    }
+}
+
+// routes name the least role they need with one of these markers, a new one
+// is added alongside the first route that needs it
+pub trait RequiredRole: Send + Sync {
+    const ROLE: Role;
+}
+
+pub enum Manager {}
+pub enum Admin {}
+
+impl RequiredRole for Manager {
+    const ROLE: Role = Role::Manager;
+}
+
+impl RequiredRole for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+// a logged in user with at least the role R, e.g. `Authorized<Manager>`
+pub struct Authorized<R>(pub CurrentUser, pub PhantomData<R>);
+
+#[async_trait]
+impl<S, R> FromRequestParts<S> for Authorized<R>
+where
+    S: Send + Sync,
+    Key: FromRef<S>,
+    R: RequiredRole,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = CurrentUser::from_request_parts(parts, state).await?;
+        if user.role < R::ROLE {
+            tracing::warn!(
+                account = %user.account,
+                role = user.role.name(),
+                required = R::ROLE.name(),
+                "denied access for missing role"
+            );
+            return Err(AppError::Forbidden(
+                "Your account doesn't have access to this page.".to_string(),
+            ));
+        }
+        Ok(Authorized(user, PhantomData))
+    }
+}
@@ -1,12 +1,18 @@
-use crate::DB;
-use askama::Template;
-use askama_axum::IntoResponse;
-use axum::{
-    extract::{Form, Path},
-    http::StatusCode,
-    response::{Redirect, Response, Result},
+use std::sync::Arc;
+
+use crate::{
+    auth::{CurrentUser, OptionalUser, VerifiedUser},
+    config::Config,
+    db::Timed,
+    error::AppError,
+    holds::{hold_seats, release_seats},
+    purchase::Cart,
+    seatmap::{check_bookable, showtime_layout, Position, SeatRow, SeatType},
+    DB,
 };
-use axum_extra::extract::PrivateCookieJar;
+use askama::Template;
+use axum::extract::{Form, Path, State};
+use axum_extra::extract::Query;
 use serde::{Deserialize, Serialize};
 use surrealdb::sql::Thing;
 
@@ -15,14 +21,16 @@ use surrealdb::sql::Thing;
 pub struct ConfirmationPage {
     pub id: String,
     pub time: String,
-    pub seat: i32,
+    pub seats: Vec<String>,
+    pub cart: String,
+    pub hold_minutes: u32,
     pub movie: Movie,
 }
 #[derive(Template)]
 #[template(path = "seating.html")]
 pub struct SeatingPage {
     pub id: String,
-    pub seats: Vec<Seat>,
+    pub rows: Vec<SeatRow>,
 }
 
 #[derive(Template)]
@@ -34,8 +42,37 @@ pub struct Times {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Seat {
     pub available: bool,
+    pub held: bool,
     pub seat: i32,
     pub id: Thing,
+    #[serde(skip)]
+    pub position: Option<Position>,
+}
+
+impl Seat {
+    pub fn kind(&self) -> SeatType {
+        self.position
+            .as_ref()
+            .map_or(SeatType::Standard, |position| position.kind)
+    }
+
+    pub fn number(&self) -> String {
+        match &self.position {
+            Some(position) => position.number.to_string(),
+            None => self.seat.to_string(),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match &self.position {
+            Some(position) => format!("{position} ({})", position.kind.name()),
+            None => format!("Seat {}", self.seat),
+        }
+    }
+
+    pub fn bookable(&self) -> bool {
+        self.available && !self.held && self.kind() != SeatType::Blocked
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -56,12 +93,6 @@ struct MovieTime {
     time: String,
 }
 
-#[derive(Deserialize)]
-struct Record {
-    #[allow(dead_code)]
-    id: Thing,
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ShowInformation {
     day: i32,
@@ -79,12 +110,19 @@ pub struct Time {
     time: String,
 }
 
-pub async fn select_seat(jar: PrivateCookieJar, Path((id, seat)): Path<(String, i32)>) -> Response {
-    if let Err(err) = check_session(&jar).await {
-        return err;
-    }
-
-    let query = DB
+pub async fn select_seats(
+    VerifiedUser(user): VerifiedUser,
+    State(config): State<Arc<Config>>,
+    Path(id): Path<String>,
+    Query(cart): Query<Cart>,
+) -> Result<ConfirmationPage, AppError> {
+    let seats: Vec<i32> = cart.tickets()?.into_iter().map(|(seat, _)| seat).collect();
+    let layout = showtime_layout(&id).await?;
+    check_bookable(&layout, &seats)?;
+    let hold_minutes = config.seating.hold_minutes;
+    hold_seats(&user.session, &id, &seats, hold_minutes).await?;
+
+    let mut query = DB
         .query(
             r#"
             SELECT (<-showing<-theaters<-playing<-movies.*)[0] AS movie,
@@ -93,55 +131,79 @@ pub async fn select_seat(jar: PrivateCookieJar, Path((id, seat)): Path<(String,
             "#,
         )
         .bind(("id", &id))
-        .await;
-    let Ok(mut query) = query else {
-        return StatusCode::NOT_ACCEPTABLE.into_response();
-    };
-    let Ok(Some(MovieTime { movie, time })) = query.take(0) else {
-        return StatusCode::NOT_ACCEPTABLE.into_response();
+        .timed()
+        .await?;
+    let Some(MovieTime { movie, time }) = query.take(0)? else {
+        return Err(AppError::NotFound);
     };
 
-    ConfirmationPage {
+    let cart = seats
+        .iter()
+        .map(|seat| format!("seats={seat}"))
+        .collect::<Vec<_>>()
+        .join("&");
+    let seats = seats.iter().map(|seat| layout.label(*seat)).collect();
+    Ok(ConfirmationPage {
         id,
         time,
-        seat,
+        seats,
+        cart,
+        hold_minutes,
         movie,
-    }
-    .into_response()
+    })
 }
 
-pub async fn seating(Path(id): Path<String>) -> Result<SeatingPage> {
+pub async fn seating(
+    OptionalUser(user): OptionalUser,
+    Path(id): Path<String>,
+) -> Result<SeatingPage, AppError> {
     let Some((_, id)) = id.split_once(':') else {
-        return Err(StatusCode::NOT_ACCEPTABLE.into());
+        return Err(AppError::BadRequest(format!("{id} is not a showtime")));
     };
-    let query = DB
+    let session = user.map(|user| user.session);
+    seating_page(id, session.as_deref()).await
+}
+
+pub async fn release(user: CurrentUser, Path(id): Path<String>) -> Result<SeatingPage, AppError> {
+    release_seats(&user.session, &id).await?;
+    seating_page(&id, Some(&user.session)).await
+}
+
+// seats held by the session itself are shown as available to it
+async fn seating_page(id: &str, session: Option<&str>) -> Result<SeatingPage, AppError> {
+    let session = session.map(|session| Thing::from(("sessions", session)));
+    let mut query = DB
         .query(
-            r#"            
-            SELECT VALUE ->showtime_seat->seats.* 
-            FROM ONLY type::thing("showtime", $id);            
+            r#"
+            SELECT id, seat, available,
+            (held_until != NONE AND held_until > time::now() AND held_by != $holder) AS held
+            FROM (
+                SELECT VALUE ->showtime_seat->seats
+                FROM ONLY type::thing("showtime", $id)
+            );
             "#,
         )
-        .bind(("id", &id))
-        .await;
-    let Ok(mut query) = query else {
-        return Err(StatusCode::NOT_ACCEPTABLE.into());
-    };
-    let Ok(mut seats): Result<Vec<Seat>, _> = query.take(0) else {
-        return Err(StatusCode::NOT_ACCEPTABLE.into());
-    };
-    seats.sort_by(|a, b| a.seat.cmp(&b.seat));
+        .bind(("id", id))
+        .bind(("holder", session))
+        .timed()
+        .await?;
+    let seats: Vec<Seat> = query.take(0)?;
+    let rows = showtime_layout(id).await?.rows(seats);
     Ok(SeatingPage {
         id: id.to_string(),
-        seats,
+        rows,
     })
 }
 
-pub async fn times(Path(id): Path<String>, Form(Day { day }): Form<Day>) -> Result<Times> {
+pub async fn times(
+    Path(id): Path<String>,
+    Form(Day { day }): Form<Day>,
+) -> Result<Times, AppError> {
     let Some((_, id)) = id.split_once(':') else {
-        return Err(StatusCode::NOT_ACCEPTABLE.into());
+        return Err(AppError::BadRequest(format!("{id} is not a theater")));
     };
 
-    let query = DB
+    let mut query = DB
         .query(
             r#" 
             SELECT id, time::format(time, "%k:%M") AS time
@@ -153,25 +215,8 @@ pub async fn times(Path(id): Path<String>, Form(Day { day }): Form<Day>) -> Resu
         )
         .bind(("id", id))
         .bind(("day", day))
-        .await;
-    let Ok(mut query) = query else {
-        return Err(StatusCode::NOT_ACCEPTABLE.into());
-    };
-    let Ok(times): Result<Vec<Time>, _> = query.take(0) else {
-        return Err(StatusCode::NOT_ACCEPTABLE.into());
-    };
+        .timed()
+        .await?;
+    let times: Vec<Time> = query.take(0)?;
     Ok(Times { times })
 }
-
-async fn check_session(jar: &PrivateCookieJar) -> Result<&PrivateCookieJar, Response> {
-    let Some(session) = jar.get("session") else {
-        return Err(Redirect::to("/login").into_response());
-    };
-    let Ok(Some(_)) = DB
-        .select::<Option<Record>>(("sessions", session.value()))
-        .await
-    else {
-        return Err(Redirect::to("/login").into_response());
-    };
-    Ok(jar)
-}
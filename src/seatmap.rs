@@ -0,0 +1,333 @@
+use std::fmt;
+
+use crate::{db::Timed, error::AppError, seating::Seat, DB};
+
+// rows of the default layout when a theater has none
+const GRID_WIDTH: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeatType {
+    Standard,
+    Premium,
+    Wheelchair,
+    Companion,
+    Blocked,
+}
+
+impl SeatType {
+    fn from_char(cell: char) -> Option<Option<SeatType>> {
+        match cell {
+            's' => Some(Some(SeatType::Standard)),
+            'p' => Some(Some(SeatType::Premium)),
+            'w' => Some(Some(SeatType::Wheelchair)),
+            'c' => Some(Some(SeatType::Companion)),
+            'x' => Some(Some(SeatType::Blocked)),
+            '_' => Some(None),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SeatType::Standard => "standard",
+            SeatType::Premium => "premium",
+            SeatType::Wheelchair => "wheelchair",
+            SeatType::Companion => "companion",
+            SeatType::Blocked => "blocked",
+        }
+    }
+
+    pub fn color(self) -> &'static str {
+        match self {
+            SeatType::Standard => "bg-blue-500",
+            SeatType::Premium => "bg-purple-500",
+            SeatType::Wheelchair => "bg-teal-500",
+            SeatType::Companion => "bg-cyan-500",
+            SeatType::Blocked => "bg-gray-400",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position {
+    pub row: String,
+    pub number: usize,
+    pub kind: SeatType,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Row {}, Seat {}", self.row, self.number)
+    }
+}
+
+// a layout is one string per row, front to back, with a character per place:
+// s standard, p premium, w wheelchair, c companion, x blocked and _ for an
+// aisle or gap. seats are numbered 1, 2, .. across the rows from the front left
+#[derive(Debug, Clone)]
+pub enum Layout {
+    Grid,
+    Rows(Vec<Vec<Option<SeatType>>>),
+}
+
+pub struct SeatRow {
+    pub row: String,
+    pub cells: Vec<Option<Seat>>,
+}
+
+impl Layout {
+    pub fn parse(rows: &[String]) -> Result<Layout, String> {
+        let mut parsed = Vec::new();
+        for (index, row) in rows.iter().enumerate() {
+            let cells = row
+                .chars()
+                .map(|cell| {
+                    SeatType::from_char(cell).ok_or_else(|| {
+                        format!("row {} has an unknown place '{cell}'", row_name(index))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            parsed.push(cells);
+        }
+        Ok(Layout::Rows(parsed))
+    }
+
+    pub fn position(&self, seat: i32) -> Option<Position> {
+        let index = usize::try_from(seat).ok()?.checked_sub(1)?;
+        match self {
+            Layout::Grid => Some(Position {
+                row: row_name(index / GRID_WIDTH),
+                number: index % GRID_WIDTH + 1,
+                kind: SeatType::Standard,
+            }),
+            Layout::Rows(rows) => {
+                let mut remaining = index;
+                for (row, cells) in rows.iter().enumerate() {
+                    for (number, kind) in cells.iter().flatten().enumerate() {
+                        if remaining == 0 {
+                            return Some(Position {
+                                row: row_name(row),
+                                number: number + 1,
+                                kind: *kind,
+                            });
+                        }
+                        remaining -= 1;
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    pub fn label(&self, seat: i32) -> String {
+        match self.position(seat) {
+            Some(position) => position.to_string(),
+            None => format!("Seat {seat}"),
+        }
+    }
+
+    // lays the showtime's seats out in the shape of the room, seats the
+    // layout has no place for are left out
+    pub fn rows(&self, mut seats: Vec<Seat>) -> Vec<SeatRow> {
+        seats.sort_by_key(|seat| seat.seat);
+        let last = seats.last().map_or(0, |seat| seat.seat);
+        let mut seats = seats.into_iter().peekable();
+        let mut next = 1;
+        let mut take = || {
+            let seat_num = next;
+            next += 1;
+            while seats.next_if(|seat| seat.seat < seat_num).is_some() {}
+            let mut seat = seats.next_if(|seat| seat.seat == seat_num)?;
+            seat.position = self.position(seat_num);
+            Some(seat)
+        };
+
+        match self {
+            Layout::Grid => {
+                let mut rows = Vec::new();
+                let mut cells = Vec::new();
+                for _ in 1..=last {
+                    cells.push(take());
+                    if cells.len() == GRID_WIDTH {
+                        rows.push(std::mem::take(&mut cells));
+                    }
+                }
+                if !cells.is_empty() {
+                    rows.push(cells);
+                }
+                rows.into_iter()
+                    .enumerate()
+                    .map(|(row, cells)| SeatRow {
+                        row: row_name(row),
+                        cells,
+                    })
+                    .collect()
+            }
+            Layout::Rows(rows) => rows
+                .iter()
+                .enumerate()
+                .map(|(row, places)| SeatRow {
+                    row: row_name(row),
+                    cells: places
+                        .iter()
+                        .map(|place| place.and_then(|_| take()))
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+// A..Z, then AA, AB, ..
+fn row_name(index: usize) -> String {
+    let letter = |n: usize| char::from(b'A' + (n % 26) as u8);
+    if index < 26 {
+        letter(index).to_string()
+    } else {
+        format!("{}{}", letter(index / 26 - 1), letter(index))
+    }
+}
+
+pub async fn showtime_layout(showtime: &str) -> Result<Layout, AppError> {
+    let mut query = DB
+        .query(
+            r#"
+            SELECT VALUE (<-showing<-theaters.layout)[0]
+            FROM ONLY type::thing("showtime", $id)
+            "#,
+        )
+        .bind(("id", showtime))
+        .timed()
+        .await?;
+    let rows: Option<Vec<String>> = query.take(0)?;
+    Ok(layout(rows))
+}
+
+pub fn layout(rows: Option<Vec<String>>) -> Layout {
+    let Some(rows) = rows else {
+        return Layout::Grid;
+    };
+    Layout::parse(&rows).unwrap_or_else(|err| {
+        tracing::warn!("invalid theater layout, using the default grid: {err}");
+        Layout::Grid
+    })
+}
+
+pub fn check_bookable(layout: &Layout, seats: &[i32]) -> Result<(), AppError> {
+    for seat in seats {
+        match layout.position(*seat) {
+            Some(position) if position.kind != SeatType::Blocked => {}
+            _ => {
+                return Err(AppError::BadRequest(format!(
+                    "{} can't be booked.",
+                    layout.label(*seat)
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use surrealdb::sql::Thing;
+
+    use super::*;
+
+    fn layout(rows: &[&str]) -> Layout {
+        let rows: Vec<String> = rows.iter().map(|row| row.to_string()).collect();
+        Layout::parse(&rows).unwrap()
+    }
+
+    fn seat(seat: i32) -> Seat {
+        Seat {
+            available: true,
+            held: false,
+            seat,
+            id: Thing::from(("seats", seat.to_string().as_str())),
+            position: None,
+        }
+    }
+
+    fn numbers(row: &SeatRow) -> Vec<Option<i32>> {
+        row.cells
+            .iter()
+            .map(|cell| cell.as_ref().map(|seat| seat.seat))
+            .collect()
+    }
+
+    #[test]
+    fn parse_rejects_unknown_places() {
+        let rows = vec!["ss_s".to_string(), "sq".to_string()];
+        assert_eq!(
+            Layout::parse(&rows).unwrap_err(),
+            "row B has an unknown place 'q'"
+        );
+    }
+
+    #[test]
+    fn positions_skip_gaps_and_run_across_rows() {
+        let layout = layout(&["s_sp", "wc_x"]);
+        let position = |seat| layout.position(seat).map(|p| (p.row, p.number, p.kind));
+        assert_eq!(position(1), Some(("A".to_string(), 1, SeatType::Standard)));
+        assert_eq!(position(3), Some(("A".to_string(), 3, SeatType::Premium)));
+        assert_eq!(
+            position(4),
+            Some(("B".to_string(), 1, SeatType::Wheelchair))
+        );
+        assert_eq!(position(6), Some(("B".to_string(), 3, SeatType::Blocked)));
+        assert_eq!(position(7), None);
+        assert_eq!(position(0), None);
+        assert_eq!(position(-1), None);
+    }
+
+    #[test]
+    fn grid_positions_wrap_every_row() {
+        let position = |seat| Layout::Grid.position(seat).map(|p| (p.row, p.number));
+        assert_eq!(position(1), Some(("A".to_string(), 1)));
+        assert_eq!(position(9), Some(("A".to_string(), 9)));
+        assert_eq!(position(10), Some(("B".to_string(), 1)));
+        assert_eq!(position(26 * 9 + 1), Some(("AA".to_string(), 1)));
+    }
+
+    #[test]
+    fn rows_place_seats_in_the_layout() {
+        let layout = layout(&["s_s", "ss"]);
+        let rows = layout.rows(vec![seat(4), seat(2), seat(1), seat(3), seat(5)]);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].row, "A");
+        assert_eq!(numbers(&rows[0]), vec![Some(1), None, Some(2)]);
+        assert_eq!(numbers(&rows[1]), vec![Some(3), Some(4)]);
+        assert_eq!(
+            rows[1].cells[1]
+                .as_ref()
+                .unwrap()
+                .position
+                .as_ref()
+                .unwrap()
+                .number,
+            2
+        );
+    }
+
+    #[test]
+    fn rows_leave_missing_seats_empty() {
+        let layout = layout(&["sss"]);
+        let rows = layout.rows(vec![seat(1), seat(3)]);
+        assert_eq!(numbers(&rows[0]), vec![Some(1), None, Some(3)]);
+    }
+
+    #[test]
+    fn grid_rows_hold_nine_seats() {
+        let rows = Layout::Grid.rows((1..=10).map(seat).collect());
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].cells.len(), 9);
+        assert_eq!(numbers(&rows[1]), vec![Some(10)]);
+    }
+
+    #[test]
+    fn grid_rows_leave_missing_seats_empty() {
+        let rows = Layout::Grid.rows(vec![seat(4), seat(1), seat(2)]);
+        assert_eq!(numbers(&rows[0]), vec![Some(1), Some(2), None, Some(4)]);
+    }
+}
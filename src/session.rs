@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use axum_extra::extract::cookie::{Cookie, PrivateCookieJar, SameSite};
+use serde::Deserialize;
+use surrealdb::sql::Thing;
+
+use crate::{csrf::csrf_cookie, db::Timed, roles::Role, DB};
+
+const SESSION_LIFETIME: &str = "7d";
+const SESSION_IDLE_TIMEOUT: &str = "2h";
+const PURGE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Deserialize)]
+pub struct SessionAccount {
+    pub id: Thing,
+    pub email: String,
+    pub verified: bool,
+    pub role: Role,
+}
+
+pub async fn start_session(
+    jar: PrivateCookieJar,
+    user: &Thing,
+) -> surrealdb::Result<PrivateCookieJar> {
+    if let Some(old) = jar.get("session") {
+        end_session(old.value()).await?;
+    }
+
+    let mut query = DB
+        .query(
+            r#"
+            BEGIN TRANSACTION;
+
+            LET $new_session = CREATE ONLY sessions;
+
+            RELATE $new_session->account_session->$user
+                SET time = time::now(),
+                expires = time::now() + <duration> $lifetime,
+                idle_expires = time::now() + <duration> $idle;
+
+            RETURN $new_session.id;
+
+            COMMIT TRANSACTION;
+            "#,
+        )
+        .bind(("user", user))
+        .bind(("lifetime", SESSION_LIFETIME))
+        .bind(("idle", SESSION_IDLE_TIMEOUT))
+        .timed()
+        .await?;
+    let session: Option<Thing> = query.take(0)?;
+    let Some(session) = session else {
+        return Ok(jar);
+    };
+
+    let cookie = Cookie::build("session", session.id.to_raw())
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .finish();
+    // a new session gets a new csrf token as well
+    Ok(jar.add(cookie).add(csrf_cookie()))
+}
+
+pub async fn validate_session(id: &str) -> surrealdb::Result<Option<SessionAccount>> {
+    let mut query = DB
+        .query(
+            r#"
+            UPDATE account_session
+            SET idle_expires = time::now() + <duration> $idle
+            WHERE in = type::thing("sessions", $id)
+            AND expires > time::now()
+            AND idle_expires > time::now()
+            RETURN out AS id, out.email AS email, out.verified_at != NONE AS verified,
+                out.role AS role
+            "#,
+        )
+        .bind(("id", id))
+        .bind(("idle", SESSION_IDLE_TIMEOUT))
+        .timed()
+        .await?;
+    let accounts: Vec<SessionAccount> = query.take(0)?;
+    Ok(accounts.into_iter().next())
+}
+
+pub async fn end_session(id: &str) -> surrealdb::Result<()> {
+    DB.query(
+        r#"
+        BEGIN TRANSACTION;
+        DELETE account_session WHERE in = type::thing("sessions", $id);
+        DELETE type::thing("sessions", $id);
+        COMMIT TRANSACTION;
+        "#,
+    )
+    .bind(("id", id))
+    .timed()
+    .await?
+    .check()?;
+    Ok(())
+}
+
+pub async fn purge_expired_sessions() {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let query = DB
+            .query(
+                r#"
+                BEGIN TRANSACTION;
+
+                LET $expired = SELECT VALUE in FROM account_session
+                WHERE expires <= time::now() OR idle_expires <= time::now();
+
+                DELETE account_session WHERE in INSIDE $expired;
+                DELETE $expired;
+                DELETE sessions WHERE count(->account_session) = 0;
+
+                COMMIT TRANSACTION;
+                "#,
+            )
+            .timed()
+            .await;
+        if let Err(err) = query.and_then(|response| response.check()) {
+            tracing::error!("failed to purge expired sessions: {err}");
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use axum_extra::extract::cookie::Key;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// tokens sent in emailed links: "<id>.<expires>.<signature>", signed with the
+// cookie signing key for one purpose so a token for one flow can't be used in
+// another. the id names a record that decides whether the token is still unused
+pub fn sign(key: &Key, purpose: &str, id: &str, expires: DateTime<Utc>) -> String {
+    let payload = format!("{id}.{}", expires.timestamp());
+    let signature = mac(key, purpose, &payload).finalize().into_bytes();
+    format!("{payload}.{}", URL_SAFE_NO_PAD.encode(signature))
+}
+
+// the id of a token that was signed for this purpose and hasn't expired
+pub fn verify(key: &Key, purpose: &str, token: &str) -> Option<String> {
+    let (payload, signature) = token.rsplit_once('.')?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    mac(key, purpose, payload).verify_slice(&signature).ok()?;
+    let (id, expires) = payload.split_once('.')?;
+    let expires = Utc.timestamp_opt(expires.parse().ok()?, 0).single()?;
+    if expires <= Utc::now() {
+        return None;
+    }
+    Some(id.to_string())
+}
+
+fn mac(key: &Key, purpose: &str, payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key.signing()).expect("hmac takes any key length");
+    mac.update(purpose.as_bytes());
+    mac.update(b":");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn verifies_its_own_tokens() {
+        let key = Key::generate();
+        let token = sign(&key, "reset", "abc123", Utc::now() + Duration::minutes(5));
+        assert_eq!(verify(&key, "reset", &token), Some("abc123".to_string()));
+    }
+
+    #[test]
+    fn rejects_expired_tokens() {
+        let key = Key::generate();
+        let token = sign(&key, "reset", "abc123", Utc::now() - Duration::seconds(1));
+        assert_eq!(verify(&key, "reset", &token), None);
+    }
+
+    #[test]
+    fn tokens_only_work_for_their_purpose_and_key() {
+        let key = Key::generate();
+        let token = sign(&key, "reset", "abc123", Utc::now() + Duration::minutes(5));
+        assert_eq!(verify(&key, "verify", &token), None);
+        assert_eq!(verify(&Key::generate(), "reset", &token), None);
+    }
+
+    #[test]
+    fn rejects_altered_tokens() {
+        let key = Key::generate();
+        let expires = Utc::now() + Duration::minutes(5);
+        let token = sign(&key, "reset", "abc123", expires);
+        let (_, signature) = token.rsplit_once('.').unwrap();
+        let later = (expires + Duration::days(1)).timestamp();
+        assert_eq!(
+            verify(
+                &key,
+                "reset",
+                &format!("other.{}.{signature}", expires.timestamp())
+            ),
+            None
+        );
+        assert_eq!(
+            verify(&key, "reset", &format!("abc123.{later}.{signature}")),
+            None
+        );
+        assert_eq!(verify(&key, "reset", "abc123"), None);
+        assert_eq!(verify(&key, "reset", ""), None);
+    }
+}
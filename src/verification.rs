@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use askama::Template;
+use askama_axum::IntoResponse;
+use axum::{
+    extract::{Query, State},
+    response::Response,
+};
+use axum_extra::extract::cookie::Key;
+use axum_htmx::HxRequest;
+use chrono::{Duration, Utc};
+use lettre::message::MultiPart;
+use serde::Deserialize;
+use surrealdb::sql::{Datetime, Thing};
+
+use crate::{
+    auth::{CurrentUser, OptionalUser},
+    config::Config,
+    csrf::CsrfToken,
+    db::Timed,
+    error::AppError,
+    landing::Index,
+    mail::{Email, Mailer},
+    tokens, DB,
+};
+
+const PURPOSE: &str = "email_verification";
+
+#[derive(Template)]
+#[template(path = "email_verified.html")]
+pub struct EmailVerified {}
+
+#[derive(Template)]
+#[template(path = "verification_sent.html")]
+pub struct VerificationSent {
+    email: String,
+    hours: u32,
+}
+
+#[derive(Template)]
+#[template(path = "verify_email.html")]
+struct VerifyEmail<'a> {
+    link: &'a str,
+    hours: u32,
+}
+
+#[derive(Template)]
+#[template(path = "verify_email.txt")]
+struct VerifyText<'a> {
+    link: &'a str,
+    hours: u32,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyQuery {
+    token: String,
+}
+
+// a new link retires the account's earlier ones
+pub async fn send_verification(
+    config: &Config,
+    key: &Key,
+    mailer: &Mailer,
+    account: &Thing,
+    email: &str,
+) -> Result<(), AppError> {
+    let hours = config.accounts.verification_hours;
+    let expires = Utc::now() + Duration::hours(hours.into());
+    let mut query = DB
+        .query(
+            r#"
+            BEGIN TRANSACTION;
+
+            UPDATE email_verifications SET used_at = time::now() WHERE account = $account AND used_at = NONE;
+            CREATE ONLY email_verifications SET account = $account, expires = $expires RETURN VALUE id;
+
+            COMMIT TRANSACTION;
+            "#,
+        )
+        .bind(("account", account))
+        .bind(("expires", Datetime::from(expires)))
+        .timed()
+        .await?;
+    let Some(verification): Option<Thing> = query.take(1)? else {
+        return Err(AppError::Internal(anyhow::anyhow!(
+            "email verification not created"
+        )));
+    };
+
+    let token = tokens::sign(key, PURPOSE, &verification.id.to_raw(), expires);
+    let link = format!(
+        "{}/verify_email?token={token}",
+        config.server.public_url.trim_end_matches('/')
+    );
+    let body = MultiPart::alternative_plain_html(
+        VerifyText { link: &link, hours }.render()?,
+        VerifyEmail { link: &link, hours }.render()?,
+    );
+    mailer.send_later(Email {
+        to: email.to_string(),
+        subject: "Verify your email address".to_string(),
+        body,
+    });
+    Ok(())
+}
+
+pub async fn verify_email(
+    OptionalUser(user): OptionalUser,
+    CsrfToken(csrf): CsrfToken,
+    HxRequest(htmx): HxRequest,
+    State(key): State<Key>,
+    Query(VerifyQuery { token }): Query<VerifyQuery>,
+) -> Result<Response, AppError> {
+    if !htmx {
+        return Ok(Index {
+            logged_in: user.is_some(),
+            content: format!("/verify_email?token={token}"),
+            csrf,
+        }
+        .into_response());
+    }
+
+    let Some(verification) = tokens::verify(&key, PURPOSE, &token) else {
+        return Err(invalid_link());
+    };
+    let response = DB
+        .query(
+            r#"
+            BEGIN TRANSACTION;
+
+            LET $used = UPDATE type::thing("email_verifications", $verification) SET used_at = time::now()
+                WHERE used_at = NONE AND expires > time::now();
+            IF array::len($used) = 0 {
+                THROW "verification link already used";
+            };
+            LET $account = $used[0].account;
+            UPDATE $account SET verified_at = time::now();
+
+            COMMIT TRANSACTION;
+            "#,
+        )
+        .bind(("verification", verification))
+        .timed()
+        .await?;
+    if response.check().is_err() {
+        return Err(invalid_link());
+    }
+    Ok(EmailVerified {}.into_response())
+}
+
+pub async fn resend_verification(
+    user: CurrentUser,
+    State(config): State<Arc<Config>>,
+    State(key): State<Key>,
+    State(mailer): State<Mailer>,
+) -> Result<VerificationSent, AppError> {
+    if user.verified {
+        return Err(AppError::BadRequest(
+            "Your email address is already verified.".to_string(),
+        ));
+    }
+    send_verification(&config, &key, &mailer, &user.account, &user.email).await?;
+    Ok(VerificationSent {
+        email: user.email,
+        hours: config.accounts.verification_hours,
+    })
+}
+
+fn invalid_link() -> AppError {
+    AppError::BadRequest(
+        "This verification link is invalid, used or expired. Log in and resend it from your Tickets page."
+            .to_string(),
+    )
+}